@@ -2,26 +2,21 @@ use euclid::*;
 use intcode;
 use std::collections::HashMap;
 use std::io;
-use std::io::Read;
 
 type Point = default::Point2D<isize>;
 
 fn main() {
-    let input = input();
-
-    let program: Vec<i64> = input
-        .split(',')
-        .map(|s| s.trim().parse().expect(s))
-        .collect();
+    let intcode::LoadedProgram { program, hash } =
+        intcode::read_program_with_hash(&mut io::stdin()).expect("Couldn't read input");
+    println!("Input hash: {:016x}", hash);
 
     let mut state = State {
         position: Point::new(0, 0),
         heading: Direction::Up,
         painted: HashMap::new(),
-        relative_base: 0,
         next_output: NextOutput::Paint,
     };
-    intcode::run_intcode(program.clone(), &mut state);
+    intcode::run_program(program.clone(), &mut state).expect("Intcode program failed");
 
     println!("Part 1: {}", state.painted.len());
 
@@ -29,10 +24,9 @@ fn main() {
         position: Point::new(0, 0),
         heading: Direction::Up,
         painted: [(Point::new(0, 0), WHITE)].iter().cloned().collect(),
-        relative_base: 0,
         next_output: NextOutput::Paint,
     };
-    intcode::run_intcode(program.clone(), &mut state);
+    intcode::run_program(program.clone(), &mut state).expect("Intcode program failed");
     println!("Part 2:\n{}", render(state.painted));
 }
 
@@ -83,7 +77,6 @@ struct State {
     position: Point,
     heading: Direction,
     painted: HashMap<Point, i64>,
-    relative_base: i64,
     next_output: NextOutput,
 }
 #[derive(Copy, Clone)]
@@ -92,13 +85,15 @@ enum NextOutput {
     Turn,
 }
 
-impl intcode::State for State {
+impl intcode::Input for State {
     fn input(&mut self) -> Option<i64> {
         Some(match self.painted.get(&self.position) {
             None => BLACK,
             Some(c) => *c,
         })
     }
+}
+impl intcode::Output for State {
     fn output(&mut self, val: i64) {
         match self.next_output {
             NextOutput::Paint => {
@@ -112,16 +107,6 @@ impl intcode::State for State {
             }
         }
     }
-
-    fn copy_output(&self) -> Vec<i64> {
-        vec![]
-    }
-    fn adjust_relative_base(&mut self, adj: i64) {
-        self.relative_base += adj;
-    }
-    fn relative_base(&self) -> i64 {
-        self.relative_base
-    }
 }
 
 fn render(panels: HashMap<Point, i64>) -> String {
@@ -149,14 +134,6 @@ fn render(panels: HashMap<Point, i64>) -> String {
     result
 }
 
-fn input() -> String {
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
-        .expect("Couldn't read input");
-    input
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
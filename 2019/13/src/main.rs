@@ -3,19 +3,26 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::env;
 use std::fmt::Display;
+use std::fs;
 use std::io;
 use std::thread;
 use std::time::Duration;
 
-fn main() {
-    let input = read_file(&env::args().nth(1).unwrap());
+const USAGE: &str = "Usage: 13 <program file> [--record log | --replay log]";
 
-    let mut program: Vec<i64> = input
-        .split(',')
-        .map(|s| s.trim().parse().expect(s))
-        .collect();
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let intcode::LoadedProgram { mut program, hash } =
+        intcode::load_program_with_hash(args.get(1).expect(USAGE)).expect("Couldn't read program");
+    println!("Input hash: {:016x}", hash);
+    let log = match (args.get(2).map(String::as_str), args.get(3)) {
+        (None, _) => Log::None,
+        (Some("--record"), Some(path)) => Log::Record(path.clone()),
+        (Some("--replay"), Some(path)) => Log::Replay(path.clone()),
+        _ => panic!("{}", USAGE),
+    };
 
-    let result = intcode::run_intcode_input(program.clone(), &[]);
+    let result = intcode::run_intcode_input(program.clone(), &[]).expect("Intcode program failed");
 
     println!(
         "Part 1: {}",
@@ -28,7 +35,7 @@ fn main() {
 
     program[0] = 2;
 
-    println!("Part 2: {}", part2(program));
+    println!("Part 2: {}", part2(program, log));
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -64,7 +71,6 @@ impl Display for Tile {
 #[derive(Default)]
 struct Game {
     next_tile: Vec<i64>,
-    relative_base: i64,
     display: HashMap<Point2, Tile>,
     ball: Point2,
     ball_direction: Vector2,
@@ -106,7 +112,7 @@ fn _read_paddle() -> i64 {
     }
 }
 
-impl intcode::State for Game {
+impl intcode::Input for Game {
     fn input(&mut self) -> Option<i64> {
         thread::sleep(Duration::from_millis(1));
         self.print();
@@ -135,6 +141,8 @@ impl intcode::State for Game {
             }
         })
     }
+}
+impl intcode::Output for Game {
     fn output(&mut self, out: i64) {
         self.next_tile.push(out);
         if self.next_tile.len() == 3 {
@@ -153,20 +161,40 @@ impl intcode::State for Game {
             self.next_tile.clear();
         }
     }
-    fn copy_output(&self) -> Vec<i64> {
-        vec![self.score]
-    }
-    fn adjust_relative_base(&mut self, adj: i64) {
-        self.relative_base += adj;
-    }
-    fn relative_base(&self) -> i64 {
-        self.relative_base
-    }
+}
+
+// Whether part 2 should record the game, or replay a recorded one instead of
+// playing.
+enum Log {
+    None,
+    Record(String),
+    Replay(String),
 }
 
 // Returns the score
-fn part2(program: Vec<i64>) -> i64 {
+fn part2(program: Vec<i64>, log: Log) -> i64 {
     let mut game: Game = Default::default();
-    let result = intcode::run_intcode(program.clone(), &mut game);
-    result.output[0]
+    let mut machine = intcode::Machine::new(program);
+    match log {
+        Log::None => {
+            machine
+                .run_device(&mut game)
+                .expect("Intcode program failed");
+        }
+        Log::Record(path) => {
+            let mut recording = intcode::Recording::new();
+            machine
+                .run_recorded(&mut game, &mut recording)
+                .expect("Intcode program failed");
+            fs::write(path, recording.to_string()).expect("Couldn't write log");
+        }
+        Log::Replay(path) => {
+            let recording: intcode::Recording = read_file(&path).parse().expect("Invalid log");
+            intcode::Replay::new(&recording)
+                .run(&mut machine, &mut game)
+                .expect("Replay failed");
+            game.print();
+        }
+    }
+    game.score
 }
@@ -1,25 +1,18 @@
 use advent_util::Point2;
 use intcode;
-use std::char;
 use std::collections::HashMap;
 use std::io;
-use std::io::Read;
 use std::str;
 
 fn main() {
-    let input = input();
-
-    let mut program: Vec<i64> = input
-        .split(',')
-        .map(|s| s.trim().parse().expect(s))
-        .collect();
-
-    let result = intcode::run_intcode_input(program.clone(), &[]);
-    let scaffold: String = result
-        .output
-        .iter()
-        .map(|c| char::from_u32(*c as u32).unwrap())
-        .collect();
+    let intcode::LoadedProgram { mut program, hash } =
+        intcode::read_program_with_hash(&mut io::stdin()).expect("Couldn't read input");
+    println!("Input hash: {:016x}", hash);
+
+    let scaffold = intcode::run_ascii(program.clone(), &[])
+        .expect("Intcode program failed")
+        .text()
+        .to_string();
     println!("{}", scaffold);
 
     println!("Part 1: {}", part1(&scaffold));
@@ -44,27 +37,21 @@ fn main() {
     // C=L,12,R,6,L,8,
     // Program=A,B,A,B,C,C,B,A,B,C
 
-    let input = "A,B,A,B,C,C,B,A,B,C
-L,12,L,6,L,8,R,6
-L,8,L,8,R,4,R,6,R,6
-L,12,R,6,L,8
-n
-";
-
-    let result = intcode::run_intcode_input(
+    let result = intcode::run_ascii(
         program.clone(),
-        &input.chars().map(|c| c as i64).collect::<Vec<i64>>(),
-    );
-
-    println!("Part 2: {:?}", result.output);
-}
-
-fn input() -> String {
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
-        .expect("Couldn't read input");
-    input
+        &[
+            "A,B,A,B,C,C,B,A,B,C",
+            "L,12,L,6,L,8,R,6",
+            "L,8,L,8,R,4,R,6,R,6",
+            "L,12,R,6,L,8",
+            "n",
+        ],
+    )
+    .expect("Intcode program failed");
+
+    // The robot reports the dust it collected as its last output.
+    let dust = result.results().last().expect("No dust count");
+    println!("Part 2: {}", dust);
 }
 
 #[derive(PartialEq, Eq, Debug)]
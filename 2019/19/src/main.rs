@@ -1,12 +1,11 @@
 use advent_util::*;
+use intcode::{Machine, RunResult, Snapshot};
+use std::io;
 
 fn main() {
-    let input = input();
-
-    let program: Vec<i64> = input
-        .split(',')
-        .map(|s| s.trim().parse().expect(s))
-        .collect();
+    let intcode::LoadedProgram { program, hash } =
+        intcode::read_program_with_hash(&mut io::stdin()).expect("Couldn't read input");
+    println!("Input hash: {:016x}", hash);
 
     println!("Part 1: {}", part1(&program));
     let ship = part2(&program);
@@ -17,20 +16,42 @@ fn main() {
     );
 }
 
+// Runs the drone program once per point, restoring it to its initial state
+// instead of reloading it each time.
+struct Drone {
+    machine: Machine,
+    start: Snapshot,
+}
+impl Drone {
+    fn new(program: &[i64]) -> Drone {
+        let machine = Machine::new(program.to_vec());
+        let start = machine.snapshot();
+        Drone { machine, start }
+    }
+    fn probe(&mut self, p: Point2) -> i64 {
+        self.machine.restore(&self.start);
+        self.machine.push_input(p.x);
+        self.machine.push_input(p.y);
+        match self.machine.run().expect("Intcode program failed") {
+            RunResult::Output(pulled) => pulled,
+            result => panic!("Expected output from the drone, got {:?}", result),
+        }
+    }
+}
+
 fn part1(program: &Vec<i64>) -> i64 {
+    let mut drone = Drone::new(program);
     let mut tractor_area: i64 = 0;
     for x in 0..50 {
         for y in 0..50 {
-            let result = intcode::run_intcode_input(program.clone(), &[x, y]);
-            tractor_area += result.output[0];
+            tractor_area += drone.probe(point2(x, y));
         }
     }
     tractor_area
 }
 
-fn test_point(p: Point2, program: &Vec<i64>) -> bool {
-    let result = intcode::run_intcode_input(program.clone(), &[p.x, p.y]);
-    result.output[0] == 1
+fn test_point(p: Point2, drone: &mut Drone) -> bool {
+    drone.probe(p) == 1
 }
 
 fn bottom_left(r: &Rect) -> Point2 {
@@ -41,15 +62,16 @@ fn top_right(r: &Rect) -> Point2 {
 }
 
 fn part2(program: &Vec<i64>) -> Rect {
+    let mut drone = Drone::new(program);
     let mut ship = Rect::new(point2(1, 1), size2(99, 99));
     let mut fits = false;
     while !fits {
         fits = true;
-        if !test_point(bottom_left(&ship), program) {
+        if !test_point(bottom_left(&ship), &mut drone) {
             fits = false;
             ship = ship.translate(vec2(1, 0));
         }
-        if !test_point(top_right(&ship), program) {
+        if !test_point(top_right(&ship), &mut drone) {
             fits = false;
             ship = ship.translate(vec2(0, 1));
         }
@@ -1,3 +1,5 @@
+use intcode::{Dialect, Machine, Solver, Target};
+
 fn main() {
     let initial_memory = vec![
         1, 0, 0, 3, 1, 1, 2, 3, 1, 3, 4, 3, 1, 5, 0, 3, 2, 1, 10, 19, 1, 19, 5, 23, 1, 6, 23, 27,
@@ -8,45 +10,26 @@ fn main() {
         127, 1, 127, 9, 0, 99, 2, 0, 14, 0,
     ];
     println!("Part 1: {:?}", run_with_args(initial_memory.clone(), 12, 2));
-    for noun in 0..100 {
-        for verb in 0..100 {
-            if run_with_args(initial_memory.clone(), noun, verb)[0] == 19690720 {
-                println!("{}, {}", noun, verb);
-            }
-        }
+    // The result is linear in the noun and verb, so this only has to run the
+    // program a couple of times instead of trying all 10,000 pairs.
+    let mut solver = Solver::new(Machine::with_dialect(initial_memory, Dialect::Day2));
+    let noun = solver.memory_var(1, 0..=99);
+    let verb = solver.memory_var(2, 0..=99);
+    for values in solver.solve(Target::Memory(0), 19690720).assignments {
+        println!("{}, {}", values[noun], values[verb]);
     }
 }
 
-fn run_with_args(mut memory: Vec<i32>, noun: i32, verb: i32) -> Vec<i32> {
+fn run_with_args(mut memory: Vec<i64>, noun: i64, verb: i64) -> Vec<i64> {
     memory[1] = noun;
     memory[2] = verb;
     return run_intcode(memory);
 }
 
-fn run_intcode(mut program: Vec<i32>) -> Vec<i32> {
-    let mut position = 0;
-    loop {
-        match program[position] {
-            1 => {
-                let arg1_pos = program[position + 1] as usize;
-                let arg2_pos = program[position + 2] as usize;
-                let result_pos = program[position + 3] as usize;
-                program[result_pos] = program[arg1_pos] + program[arg2_pos];
-            }
-            2 => {
-                let arg1_pos = program[position + 1] as usize;
-                let arg2_pos = program[position + 2] as usize;
-                let result_pos = program[position + 3] as usize;
-                program[result_pos] = program[arg1_pos] * program[arg2_pos];
-            }
-            99 => return program,
-            _ => panic!(
-                "Unexpected opcode at position {}: {}",
-                position, program[position]
-            ),
-        }
-        position += 4;
-    }
+fn run_intcode(program: Vec<i64>) -> Vec<i64> {
+    let mut machine = Machine::with_dialect(program, Dialect::Day2);
+    machine.run().expect("Intcode program failed");
+    machine.into_memory()
 }
 
 #[cfg(test)]
@@ -1,48 +1,25 @@
 use intcode::*;
 use std::io;
-use std::io::Read;
 
 fn main() {
-    let input = input();
+    let LoadedProgram { program, hash } =
+        read_program_with_hash(&mut io::stdin()).expect("Couldn't read input");
+    println!("Input hash: {:016x}", hash);
 
     println!(
         "Part 1: {:?}",
-        run_intcode_input(
-            input
-                .split(',')
-                .map(|s| s
-                    .trim()
-                    .parse()
-                    .expect(&["Must be an integer: ", s].concat()))
-                .collect(),
-            &[1]
-        )
-        .output
+        run_intcode_input(program.clone(), &[1])
+            .expect("Intcode program failed")
+            .output
     );
     println!(
         "Part 1: {:?}",
-        run_intcode_input(
-            input
-                .split(',')
-                .map(|s| s
-                    .trim()
-                    .parse()
-                    .expect(&["Must be an integer: ", s].concat()))
-                .collect(),
-            &[2]
-        )
-        .output
+        run_intcode_input(program, &[2])
+            .expect("Intcode program failed")
+            .output
     );
 }
 
-fn input() -> String {
-    let mut input = String::new();
-    io::stdin()
-        .read_to_string(&mut input)
-        .expect("Couldn't read input");
-    input
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
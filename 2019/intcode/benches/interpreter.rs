@@ -0,0 +1,163 @@
+// Compares the decode cache against decoding every instruction, and both
+// against the interpreter as it was before instructions had a fixed-size
+// representation, on day 9's programs. Run with `cargo bench`.
+use intcode::{load_program, Machine, RunResult};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+// The old interpreter's inner loop: decodes the instruction at ip on every
+// step into a Vec of modes and a boxed slice of parameters.
+mod baseline {
+    use super::*;
+
+    fn read(memory: &[i64], addr: usize) -> i64 {
+        memory.get(addr).copied().unwrap_or(0)
+    }
+
+    fn address(addr: i64) -> usize {
+        usize::try_from(addr).expect("negative address")
+    }
+
+    fn params(opcode: i64) -> usize {
+        match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => panic!("unknown opcode {}", opcode),
+        }
+    }
+
+    pub fn run(program: &[i64], input: &[i64]) -> Vec<i64> {
+        let mut memory = program.to_vec();
+        let mut input = input.iter();
+        let (mut ip, mut relative_base) = (0, 0);
+        let mut output = vec![];
+        loop {
+            let instruction = read(&memory, ip);
+            let opcode = instruction % 100;
+            let mut modes: Vec<i64> = vec![];
+            let mut allmodes = instruction / 100;
+            while allmodes != 0 {
+                modes.push(allmodes % 10);
+                allmodes /= 10;
+            }
+            modes.resize(params(opcode), 0);
+            let params: Box<[i64]> = (ip + 1..ip + 1 + params(opcode))
+                .map(|i| read(&memory, i))
+                .collect();
+            let addr = |i: usize| match modes[i] {
+                0 => address(params[i]),
+                2 => address(params[i] + relative_base),
+                mode => panic!("can't take the address of mode {}", mode),
+            };
+            let load = |i: usize| match modes[i] {
+                1 => params[i],
+                _ => read(&memory, addr(i)),
+            };
+            let (write, next) = match opcode {
+                1 => (Some((addr(2), load(0) + load(1))), ip + 4),
+                2 => (Some((addr(2), load(0) * load(1))), ip + 4),
+                3 => (
+                    Some((addr(0), *input.next().expect("Not enough input"))),
+                    ip + 2,
+                ),
+                4 => {
+                    output.push(load(0));
+                    (None, ip + 2)
+                }
+                5 if load(0) != 0 => (None, address(load(1))),
+                6 if load(0) == 0 => (None, address(load(1))),
+                5 | 6 => (None, ip + 3),
+                7 => (Some((addr(2), i64::from(load(0) < load(1)))), ip + 4),
+                8 => (Some((addr(2), i64::from(load(0) == load(1)))), ip + 4),
+                9 => {
+                    relative_base += load(0);
+                    (None, ip + 2)
+                }
+                _ => return output,
+            };
+            if let Some((addr, val)) = write {
+                if addr >= memory.len() {
+                    memory.resize(addr + 1, 0);
+                }
+                memory[addr] = val;
+            }
+            ip = next;
+        }
+    }
+}
+
+fn run(program: &[i64], input: &[i64], cache: bool) -> Vec<i64> {
+    let mut machine = Machine::new(program.to_vec());
+    machine.set_decode_cache(cache);
+    for &val in input {
+        machine.push_input(val);
+    }
+    let mut output = vec![];
+    loop {
+        match machine.run().expect("Intcode program failed") {
+            RunResult::Output(val) => output.push(val),
+            RunResult::Halted => return output,
+            RunResult::NeedsInput => panic!("Not enough input"),
+            RunResult::BudgetExhausted => unreachable!(),
+        }
+    }
+}
+
+fn time(iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    start.elapsed() / iterations
+}
+
+fn bench(name: &str, program: &[i64], input: &[i64], iterations: u32) {
+    let expected = baseline::run(program, input);
+    assert_eq!(run(program, input, false), expected);
+    assert_eq!(run(program, input, true), expected);
+    let old = time(iterations, || {
+        baseline::run(program, input);
+    });
+    let uncached = time(iterations, || {
+        run(program, input, false);
+    });
+    let cached = time(iterations, || {
+        run(program, input, true);
+    });
+    let speedup = |d: Duration| old.as_secs_f64() / d.as_secs_f64();
+    println!(
+        "{:<10} baseline {:>12?}  uncached {:>12?} ({:.2}x)  cached {:>12?} ({:.2}x)",
+        name,
+        old,
+        uncached,
+        speedup(uncached),
+        cached,
+        speedup(cached)
+    );
+}
+
+fn main() {
+    bench(
+        "quine",
+        &[
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ],
+        &[],
+        100_000,
+    );
+    bench(
+        "multiply",
+        &[1102, 34915192, 34915192, 7, 4, 7, 99, 0],
+        &[],
+        1_000_000,
+    );
+    bench("large", &[104, 1125899906842624, 99], &[], 1_000_000);
+
+    // The puzzle input's part 2 runs a few hundred thousand instructions.
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../9/input");
+    if let Ok(boost) = load_program(path) {
+        bench("boost", &boost, &[2], 20);
+    }
+}
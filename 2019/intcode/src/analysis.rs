@@ -0,0 +1,416 @@
+use super::disasm::decode_at;
+use super::{disassemble_at, Line, Mode, Opcode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+// How a basic block ends.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Exit {
+    // Runs into the next block, or off the end of memory.
+    Next,
+    // An unconditional jump to an immediate address.
+    Jump,
+    // A conditional jump to an immediate address.
+    Branch,
+    // A jump whose target is read from memory, so it isn't known statically.
+    ComputedJump,
+    Halt,
+    // The last word doesn't decode as an instruction.
+    Invalid,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EdgeKind {
+    Fallthrough,
+    Taken,
+    // A possible target of a computed jump. See analyze().
+    Indirect,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Edge {
+    // The start address of the successor block.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Block {
+    pub start: usize,
+    // One past the last word of the block.
+    pub end: usize,
+    pub lines: Vec<Line>,
+    pub exit: Exit,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ControlFlowGraph {
+    // The blocks reachable from address 0, in address order.
+    pub blocks: Vec<Block>,
+    // Runs of words that decode as instructions but that no block reaches.
+    pub unreachable: Vec<Range<usize>>,
+    // Everything else.
+    pub data: Vec<Range<usize>>,
+}
+
+// One reachable instruction, and where control can go after it.
+struct Reached {
+    len: usize,
+    exit: Exit,
+    // Taken and fallthrough successors; indirect ones are added at the end.
+    successors: Vec<Edge>,
+}
+
+// Recovers the control-flow graph of a program starting at address 0.
+//
+// Jumps with immediate targets give exact edges, and a jump whose condition
+// is immediate is treated as always or never taken. Computed jumps can't be
+// followed exactly, so if the program has any, every immediate operand that
+// points at an otherwise-unexplained instruction is assumed to be a possible
+// target. That covers the usual Intcode calling convention, which stores a
+// return address with something like `add #ret, #0 -> [rb+1]`. The analysis
+// assumes the program doesn't modify its own code.
+pub fn analyze(memory: &[i64]) -> ControlFlowGraph {
+    let mut reached: BTreeMap<usize, Reached> = BTreeMap::new();
+    let mut constants = BTreeSet::new();
+    let mut indirect_targets = BTreeSet::new();
+    let mut work = vec![0];
+    loop {
+        while let Some(address) = work.pop() {
+            if address >= memory.len() || reached.contains_key(&address) {
+                continue;
+            }
+            let inst = reach(memory, address, &mut constants);
+            work.extend(inst.successors.iter().map(|e| e.to));
+            reached.insert(address, inst);
+        }
+        if !reached.values().any(|r| r.exit == Exit::ComputedJump) {
+            break;
+        }
+        let covered = covered_words(&reached);
+        let new_targets: Vec<usize> = constants
+            .iter()
+            .copied()
+            .filter(|&c| !covered.contains(&c) && decode_at(memory, c).is_some())
+            .collect();
+        if new_targets.is_empty() {
+            break;
+        }
+        indirect_targets.extend(new_targets.iter().copied());
+        work = new_targets;
+    }
+
+    let mut leaders: BTreeSet<usize> = indirect_targets.clone();
+    leaders.insert(0);
+    for (&address, inst) in &reached {
+        if inst.exit != Exit::Next {
+            leaders.insert(address + inst.len);
+        }
+        for edge in &inst.successors {
+            if edge.kind == EdgeKind::Taken {
+                leaders.insert(edge.to);
+            }
+        }
+    }
+
+    let mut blocks = vec![];
+    for &start in leaders.iter().filter(|a| reached.contains_key(a)) {
+        let mut block = Block {
+            start,
+            end: start,
+            lines: vec![],
+            exit: Exit::Next,
+            successors: vec![],
+        };
+        let mut address = start;
+        loop {
+            let inst = &reached[&address];
+            block
+                .lines
+                .push(disassemble_at(memory, address).expect("reached addresses decode"));
+            block.end = address + inst.len;
+            address = block.end;
+            if inst.exit != Exit::Next
+                || leaders.contains(&address)
+                || !reached.contains_key(&address)
+            {
+                block.exit = inst.exit;
+                block.successors = inst.successors.clone();
+                break;
+            }
+        }
+        if block.exit == Exit::ComputedJump {
+            block
+                .successors
+                .extend(indirect_targets.iter().map(|&to| Edge {
+                    to,
+                    kind: EdgeKind::Indirect,
+                }));
+        }
+        blocks.push(block);
+    }
+
+    let covered = covered_words(&reached);
+    let mut unreachable = vec![];
+    let mut data = vec![];
+    let mut address = 0;
+    while address < memory.len() {
+        if covered.contains(&address) {
+            address += 1;
+            continue;
+        }
+        let len = match decode_at(memory, address) {
+            Some((opcode, _, _))
+                if (address..address + 1 + opcode.params()).all(|a| !covered.contains(&a)) =>
+            {
+                Some(1 + opcode.params())
+            }
+            _ => None,
+        };
+        match len {
+            Some(len) => extend_ranges(&mut unreachable, address..address + len),
+            None => extend_ranges(&mut data, address..address + 1),
+        }
+        address += len.unwrap_or(1);
+    }
+
+    ControlFlowGraph {
+        blocks,
+        unreachable,
+        data,
+    }
+}
+
+fn reach(memory: &[i64], address: usize, constants: &mut BTreeSet<usize>) -> Reached {
+    let (opcode, modes, params) = match decode_at(memory, address) {
+        Some(decoded) => decoded,
+        None => {
+            return Reached {
+                len: 1,
+                exit: Exit::Invalid,
+                successors: vec![],
+            }
+        }
+    };
+    for (&mode, &param) in modes.iter().zip(params) {
+        if mode == Mode::Immediate && param >= 0 {
+            constants.insert(param as usize);
+        }
+    }
+    let len = 1 + params.len();
+    let next = Edge {
+        to: address + len,
+        kind: EdgeKind::Fallthrough,
+    };
+    let jumps_if_zero = match opcode {
+        Opcode::Halt => {
+            return Reached {
+                len,
+                exit: Exit::Halt,
+                successors: vec![],
+            }
+        }
+        Opcode::JumpIfTrue => false,
+        Opcode::JumpIfFalse => true,
+        _ => {
+            return Reached {
+                len,
+                exit: Exit::Next,
+                successors: vec![next],
+            }
+        }
+    };
+    // None if the condition isn't known.
+    let taken = match modes[0] {
+        Mode::Immediate => Some((params[0] == 0) == jumps_if_zero),
+        _ => None,
+    };
+    if taken == Some(false) {
+        return Reached {
+            len,
+            exit: Exit::Next,
+            successors: vec![next],
+        };
+    }
+    let mut successors = vec![];
+    let exit = match modes[1] {
+        Mode::Immediate => {
+            if (0..memory.len() as i64).contains(&params[1]) {
+                successors.push(Edge {
+                    to: params[1] as usize,
+                    kind: EdgeKind::Taken,
+                });
+            }
+            if taken.is_some() {
+                Exit::Jump
+            } else {
+                Exit::Branch
+            }
+        }
+        _ => Exit::ComputedJump,
+    };
+    if taken.is_none() {
+        successors.push(next);
+    }
+    Reached {
+        len,
+        exit,
+        successors,
+    }
+}
+
+// Every word that's part of a reachable instruction.
+fn covered_words(reached: &BTreeMap<usize, Reached>) -> BTreeSet<usize> {
+    reached
+        .iter()
+        .flat_map(|(&address, inst)| address..address + inst.len)
+        .collect()
+}
+
+fn extend_ranges(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+impl ControlFlowGraph {
+    // The block containing `address`, if it's reachable code.
+    pub fn block_at(&self, address: usize) -> Option<&Block> {
+        self.blocks
+            .iter()
+            .find(|b| (b.start..b.end).contains(&address))
+    }
+
+    // The start addresses of blocks that end in a computed jump.
+    pub fn computed_jumps(&self) -> Vec<usize> {
+        self.blocks
+            .iter()
+            .filter(|b| b.exit == Exit::ComputedJump)
+            .map(|b| b.start)
+            .collect()
+    }
+
+    // Renders the graph in Graphviz's DOT language. Blocks ending in computed
+    // jumps are red, and edges to their possible targets are dashed.
+    // Unreachable code is shown as grey nodes with no edges.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph intcode {\n");
+        dot.push_str("  node [shape=box, fontname=monospace];\n");
+        for block in &self.blocks {
+            let mut label = String::new();
+            for line in &block.lines {
+                write!(label, "{}\\l", line).unwrap();
+            }
+            let color = match block.exit {
+                Exit::ComputedJump => ", color=red",
+                _ => "",
+            };
+            writeln!(dot, "  b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+        }
+        for range in &self.unreachable {
+            writeln!(
+                dot,
+                "  u{} [label=\"unreachable {}..{}\", style=dashed, color=grey];",
+                range.start, range.start, range.end
+            )
+            .unwrap();
+        }
+        for block in &self.blocks {
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Taken => " [label=taken]",
+                    EdgeKind::Indirect => " [style=dashed]",
+                };
+                writeln!(dot, "  b{} -> b{}{};", block.start, edge.to, style).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn edge(to: usize, kind: EdgeKind) -> Edge {
+        Edge { to, kind }
+    }
+
+    // Counts down from 3, outputting each value, and skips over some dead
+    // code on the way out.
+    fn countdown() -> Vec<i64> {
+        assemble(
+            "loop:  out [n]
+                   add [n], #-1 -> [n]
+                   jt [n], #loop
+                   jt #1, #done
+                   out #7
+            done:  hlt
+            n:     data 3",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn branches_and_unreachable_code() {
+        let cfg = analyze(&countdown());
+        let starts: Vec<usize> = cfg.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 9, 14]);
+        assert_eq!(cfg.blocks[0].exit, Exit::Branch);
+        assert_eq!(
+            cfg.blocks[0].successors,
+            vec![edge(0, EdgeKind::Taken), edge(9, EdgeKind::Fallthrough)]
+        );
+        assert_eq!(cfg.blocks[1].exit, Exit::Jump);
+        assert_eq!(cfg.blocks[1].successors, vec![edge(14, EdgeKind::Taken)]);
+        assert_eq!(cfg.blocks[2].exit, Exit::Halt);
+        assert_eq!(cfg.unreachable, vec![12..14]);
+        assert_eq!(cfg.data, vec![15..16]);
+        assert_eq!(cfg.block_at(10).map(|b| b.start), Some(9));
+        assert_eq!(cfg.block_at(12), None);
+    }
+
+    #[test]
+    fn computed_jumps_use_stored_return_addresses() {
+        // Calls `double` with the return address in [rb+0].
+        let program = assemble(
+            "      arb #stack
+                   add #ret, #0 -> [rb]
+                   jt #1, #double
+            ret:   out [x]
+                   hlt
+            double: mul [x], #2 -> [x]
+                   jt #1, [rb]
+            x:     data 21
+            stack: data 0",
+        )
+        .unwrap();
+        let cfg = analyze(&program);
+        let ret = 9;
+        let double = 12;
+        assert_eq!(cfg.computed_jumps(), vec![double]);
+        let call = cfg.block_at(double).unwrap();
+        assert_eq!(call.successors, vec![edge(ret, EdgeKind::Indirect)]);
+        assert_eq!(cfg.block_at(ret).unwrap().exit, Exit::Halt);
+        assert_eq!(cfg.unreachable, vec![]);
+        assert_eq!(cfg.data, vec![19..21]);
+    }
+
+    #[test]
+    fn dot() {
+        let dot = analyze(&countdown()).to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("  b0 [label=\"0000: OUT [15]\\l0002: ADD [15], #-1 -> [15]\\l0006: JT [15], #0\\l\"];\n"));
+        assert!(dot.contains("  b0 -> b0 [label=taken];\n"));
+        assert!(dot.contains("  b0 -> b9;\n"));
+        assert!(dot.contains("  u12 [label=\"unreachable 12..14\", style=dashed, color=grey];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,119 @@
+use super::{run_program, Input, IntcodeError, Output};
+use std::collections::VecDeque;
+
+// A Device for programs that talk in ASCII: input is given as lines of text,
+// and output is decoded back into text. Output values that aren't ASCII,
+// like a final answer, are kept separately as results.
+#[derive(Default)]
+pub struct AsciiState {
+    input: VecDeque<i64>,
+    text: String,
+    results: Vec<i64>,
+}
+
+impl AsciiState {
+    pub fn new() -> AsciiState {
+        AsciiState::default()
+    }
+
+    // Queues `line` followed by a newline.
+    pub fn push_line(&mut self, line: &str) {
+        self.input.extend(line.chars().map(|c| c as i64));
+        self.input.push_back('\n' as i64);
+    }
+
+    // Everything the program printed, without the results.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn lines(&self) -> std::str::Lines<'_> {
+        self.text.lines()
+    }
+
+    // Outputs that weren't ASCII characters, in order.
+    pub fn results(&self) -> &[i64] {
+        &self.results
+    }
+}
+
+// Whether `val` is an output AsciiState treats as text.
+pub fn is_ascii_output(val: i64) -> bool {
+    (0..128).contains(&val)
+}
+
+impl Input for AsciiState {
+    fn input(&mut self) -> Option<i64> {
+        self.input.pop_front()
+    }
+}
+impl Output for AsciiState {
+    fn output(&mut self, val: i64) {
+        if is_ascii_output(val) {
+            self.text.push(val as u8 as char);
+        } else {
+            self.results.push(val);
+        }
+    }
+}
+
+// Runs an ASCII program to completion with `lines` as its input.
+pub fn run_ascii(program: Vec<i64>, lines: &[&str]) -> Result<AsciiState, IntcodeError> {
+    let mut state = AsciiState::new();
+    for line in lines {
+        state.push_line(line);
+    }
+    run_program(program, &mut state)?;
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, ErrorCause};
+
+    // Echoes each input line back in upper case until it reads an empty
+    // line, then outputs 1000 plus how many characters it read.
+    fn shout() -> Vec<i64> {
+        assemble(
+            "loop:  in -> [c]
+                   eq [c], #10 -> [t]
+                   jt [t], #eol
+                   add [count], #1 -> [count]
+                   lt [c], #97 -> [t]
+                   jt [t], #out
+                   add [c], #-32 -> [c]
+            out:   out [c]
+                   add #0, #0 -> [empty]
+                   jt #1, #loop
+            eol:   out #10
+                   jt [empty], #done
+                   add #1, #0 -> [empty]
+                   jt #1, #loop
+            done:  out [count]
+                   hlt
+            c:     data 0
+            t:     data 0
+            count: data 1000
+            empty: data 1",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lines_and_results() {
+        let state = run_ascii(shout(), &["hello", "Intcode 2019", ""]).unwrap();
+        assert_eq!(state.text(), "HELLO\nINTCODE 2019\n\n");
+        assert_eq!(
+            state.lines().collect::<Vec<_>>(),
+            vec!["HELLO", "INTCODE 2019", ""]
+        );
+        assert_eq!(state.results(), &[1017]);
+    }
+
+    #[test]
+    fn running_out_of_lines() {
+        let err = run_ascii(shout(), &["hello"]).err().unwrap();
+        assert_eq!(err.cause, ErrorCause::MissingInput);
+    }
+}
@@ -0,0 +1,316 @@
+use super::{Mode, Opcode};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Assembles Intcode from a line-oriented source:
+//
+//   loop:   add [rb+3], #5 -> [counter]  ; comments start with a semicolon
+//           jt [counter], #loop
+//           hlt
+//   counter: data 0, -1, loop+2
+//
+// Mnemonics are the ones the disassembler prints (add, mul, in, out, jt, jf,
+// lt, eq, arb, hlt), in any case. Operands are `#value` for immediate mode,
+// `[address]` for position mode, and `[rb+offset]` or `[rb-offset]` for
+// relative mode. A value is a number, a label, or a label plus or minus a
+// number. `->` may be used instead of a comma before the operand an instruction
+// writes to, so the disassembler's output assembles back to the same program.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<&str, i64> = HashMap::new();
+    let mut items: Vec<(usize, Item)> = vec![];
+    let mut address = 0;
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message| AsmError {
+            line: line_number,
+            message,
+        };
+        let mut text = line.split(';').next().unwrap().trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_label(label) {
+                return Err(error(format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label, address).is_some() {
+                return Err(error(format!("label `{}` defined twice", label)));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let item = parse_item(text).map_err(error)?;
+        address += item.len() as i64;
+        items.push((line_number, item));
+    }
+
+    let mut program = vec![];
+    for (line_number, item) in items {
+        let resolve = |value: &Value| match value {
+            Value::Number(n) => Ok(*n),
+            Value::Label(label, offset) => match labels.get(label.as_str()) {
+                Some(address) => Ok(address + offset),
+                None => Err(AsmError {
+                    line: line_number,
+                    message: format!("undefined label `{}`", label),
+                }),
+            },
+        };
+        match item {
+            Item::Instruction(opcode, operands) => {
+                let mut instruction = opcode.code();
+                let mut place = 100;
+                for (mode, _) in operands.iter() {
+                    instruction += mode.code() * place;
+                    place *= 10;
+                }
+                program.push(instruction);
+                for (_, value) in operands.iter() {
+                    program.push(resolve(value)?);
+                }
+            }
+            Item::Data(values) => {
+                for value in values.iter() {
+                    program.push(resolve(value)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct AsmError {
+    // 1-based line number in the source.
+    pub line: usize,
+    pub message: String,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+impl Error for AsmError {}
+
+enum Value {
+    Number(i64),
+    // A label's address plus an offset.
+    Label(String, i64),
+}
+
+enum Item {
+    Instruction(Opcode, Vec<(Mode, Value)>),
+    Data(Vec<Value>),
+}
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Instruction(opcode, _) => opcode.params() + 1,
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+fn parse_item(text: &str) -> Result<Item, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    };
+    let rest = rest.trim_start_matches("->").replace("->", ",");
+    let args: Vec<&str> = if rest.is_empty() {
+        vec![]
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    if mnemonic.eq_ignore_ascii_case("data") {
+        if args.is_empty() {
+            return Err("`data` needs at least one value".to_string());
+        }
+        return Ok(Item::Data(
+            args.into_iter()
+                .map(parse_value)
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    let opcode = Opcode::ALL
+        .iter()
+        .copied()
+        .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+        .ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+    if args.len() != opcode.params() {
+        return Err(format!(
+            "`{}` takes {} operands, got {}",
+            mnemonic,
+            opcode.params(),
+            args.len()
+        ));
+    }
+    let operands: Vec<(Mode, Value)> = args
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<_, _>>()?;
+    if let Some(w) = opcode.write_param() {
+        if let Mode::Immediate = operands[w].0 {
+            return Err(format!(
+                "`{}` can't write to an immediate operand",
+                mnemonic
+            ));
+        }
+    }
+    Ok(Item::Instruction(opcode, operands))
+}
+
+fn parse_operand(text: &str) -> Result<(Mode, Value), String> {
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok((Mode::Immediate, parse_value(value)?));
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let inner = inner.trim();
+        if let Some(offset) = inner.strip_prefix("rb") {
+            let offset = offset.trim();
+            if offset.is_empty() {
+                return Ok((Mode::Relative, Value::Number(0)));
+            }
+            if let Some(positive) = offset.strip_prefix('+') {
+                return Ok((Mode::Relative, parse_value(positive)?));
+            }
+            if offset.starts_with('-') {
+                return Ok((Mode::Relative, parse_value(offset)?));
+            }
+        }
+        return Ok((Mode::Position, parse_value(inner)?));
+    }
+    Err(format!(
+        "invalid operand `{}`; expected #value, [address], or [rb+offset]",
+        text
+    ))
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if let Ok(n) = text.parse() {
+        return Ok(Value::Number(n));
+    }
+    let (label, offset) = match text.rfind(['+', '-']) {
+        Some(sign) if sign > 0 => {
+            let offset: i64 = text[sign + 1..]
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid offset in `{}`", text))?;
+            let offset = if &text[sign..=sign] == "-" {
+                -offset
+            } else {
+                offset
+            };
+            (text[..sign].trim(), offset)
+        }
+        _ => (text, 0),
+    };
+    if !is_label(label) {
+        return Err(format!("invalid value `{}`", text));
+    }
+    Ok(Value::Label(label.to_string(), offset))
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble, run_intcode_input};
+
+    #[test]
+    fn day5_jump_example() {
+        assert_eq!(
+            assemble(
+                "
+start:    in -> [start+3]       ; Overwrites the jt condition.
+          jt #-1, #nonzero
+          add #0, #0 -> [result]
+nonzero:  out [result]
+          HLT
+result:   data 1
+"
+            ),
+            Ok(vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1])
+        );
+    }
+
+    #[test]
+    fn relative_mode_and_data() {
+        let program = assemble(
+            "arb #table
+             out [rb]
+             out [rb-1]
+             out [rb+1]
+             hlt
+             data 7
+             table: data 8, table - 4
+             ",
+        )
+        .unwrap();
+        assert_eq!(program, vec![109, 10, 204, 0, 204, -1, 204, 1, 99, 7, 8, 6]);
+        assert_eq!(
+            run_intcode_input(program, &[]).unwrap().output,
+            vec![8, 7, 6]
+        );
+    }
+
+    #[test]
+    fn disassembly_reassembles() {
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let source: Vec<String> = disassemble(&program)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(assemble(&source.join("\n")), Ok(program));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |line, message: &str| {
+            Err(AsmError {
+                line,
+                message: message.to_string(),
+            })
+        };
+        assert_eq!(
+            assemble("hlt\n\nfoo #1"),
+            error(3, "unknown mnemonic `foo`")
+        );
+        assert_eq!(
+            assemble("add #1, #2"),
+            error(1, "`add` takes 3 operands, got 2")
+        );
+        assert_eq!(
+            assemble("in #1"),
+            error(1, "`in` can't write to an immediate operand")
+        );
+        assert_eq!(
+            assemble("hlt\njt #1, #nowhere"),
+            error(2, "undefined label `nowhere`")
+        );
+        assert_eq!(
+            assemble("a: hlt\na: hlt"),
+            error(2, "label `a` defined twice")
+        );
+        assert_eq!(
+            assemble("out 5"),
+            error(
+                1,
+                "invalid operand `5`; expected #value, [address], or [rb+offset]"
+            )
+        );
+        assert_eq!(assemble("3x: hlt"), error(1, "invalid label `3x`"));
+    }
+}
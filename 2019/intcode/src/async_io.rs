@@ -0,0 +1,121 @@
+use super::{ErrorCause, IntcodeError, Machine, Memory, RunResult};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use std::error::Error;
+use std::fmt;
+
+// Why Machine::run_async() failed: either the program did, or the output sink
+// did.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AsyncError<E> {
+    Intcode(IntcodeError),
+    Output(E),
+}
+impl<E: fmt::Display> fmt::Display for AsyncError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsyncError::Intcode(err) => err.fmt(f),
+            AsyncError::Output(err) => write!(f, "sending output: {}", err),
+        }
+    }
+}
+impl<E: fmt::Debug + fmt::Display> Error for AsyncError<E> {}
+
+impl<M: Memory> Machine<M> {
+    // Like run_device(), but awaits each input from `input` and sends each
+    // output to `output`, so a group of machines connected by channels can run
+    // as tasks on one thread. The end of `input` stops the program with
+    // ErrorCause::MissingInput.
+    //
+    // The machine only yields to other tasks while waiting on `input` or
+    // `output`. Set an instruction budget to get control back from a program
+    // that computes for a long time without doing either.
+    pub async fn run_async<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<RunResult, AsyncError<O::Error>>
+    where
+        I: Stream<Item = i64> + Unpin,
+        O: Sink<i64> + Unpin,
+    {
+        loop {
+            match self.run().map_err(AsyncError::Intcode)? {
+                RunResult::NeedsInput => match input.next().await {
+                    Some(val) => self.push_input(val),
+                    None => return Err(AsyncError::Intcode(self.error(ErrorCause::MissingInput))),
+                },
+                RunResult::Output(val) => output.send(val).await.map_err(AsyncError::Output)?,
+                result => return Ok(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::{block_on, LocalPool};
+    use futures::future::join_all;
+    use futures::stream;
+    use futures::task::LocalSpawnExt;
+
+    // The second example from day 7 part 2.
+    const AMPLIFIER: [i64; 29] = [
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28,
+        1005, 28, 6, 99, 0, 0, 5,
+    ];
+
+    #[test]
+    fn amplifier_ring_on_local_pool() {
+        let phases = [9, 8, 7, 6, 5];
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            phases.iter().map(|_| mpsc::unbounded::<i64>()).unzip();
+        for (sender, &phase) in senders.iter().zip(&phases) {
+            sender.unbounded_send(phase).unwrap();
+        }
+        senders[0].unbounded_send(0).unwrap();
+
+        let mut pool = LocalPool::new();
+        let mut handles = vec![];
+        for (i, mut input) in receivers.into_iter().enumerate() {
+            let mut output = senders[(i + 1) % phases.len()].clone();
+            let mut machine = Machine::new(AMPLIFIER.to_vec());
+            let task = async move {
+                let result = machine.run_async(&mut input, &mut output).await;
+                // Keep the input open so the last amplifier's final output
+                // has somewhere to go.
+                (result, input)
+            };
+            handles.push(pool.spawner().spawn_local_with_handle(task).unwrap());
+        }
+        drop(senders);
+
+        let mut results = pool.run_until(join_all(handles));
+        for (result, _) in &results {
+            assert_eq!(result, &Ok(RunResult::Halted));
+        }
+        let signal = results[0].1.try_recv().ok();
+        assert_eq!(signal, Some(139629729));
+    }
+
+    #[test]
+    fn end_of_input_stream() {
+        let mut machine = Machine::new(vec![3, 0, 3, 0, 99]);
+        let (mut sender, _receiver) = mpsc::unbounded();
+        let result = block_on(machine.run_async(&mut stream::iter(vec![1]), &mut sender));
+        match result {
+            Err(AsyncError::Intcode(err)) => assert_eq!(err.cause, ErrorCause::MissingInput),
+            _ => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn closed_output() {
+        let mut machine = Machine::new(vec![104, 1, 99]);
+        let (mut sender, receiver) = mpsc::unbounded();
+        drop(receiver);
+        let result = block_on(machine.run_async(&mut stream::empty(), &mut sender));
+        assert!(matches!(result, Err(AsyncError::Output(_))));
+    }
+}
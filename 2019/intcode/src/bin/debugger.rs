@@ -0,0 +1,44 @@
+use intcode::Debugger;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+// Usage: debugger <program file> [script file]
+//
+// Runs the commands in the script file, echoing each one, and then reads more
+// commands from stdin until `quit` or end of input.
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let path = args
+        .next()
+        .expect("Usage: debugger <program file> [script file]");
+    let program = intcode::load_program(&path)?;
+
+    let mut debugger = Debugger::new(program);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    if let Some(script) = args.next() {
+        for line in fs::read_to_string(&script)?.lines() {
+            writeln!(out, "(debug) {}", line)?;
+            if !debugger.command(line, &mut out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    let stdin = io::stdin();
+    loop {
+        write!(out, "(debug) ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if !debugger.command(&line, &mut out)? {
+            return Ok(());
+        }
+    }
+}
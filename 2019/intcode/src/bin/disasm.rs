@@ -0,0 +1,24 @@
+use std::env;
+
+const USAGE: &str = "Usage: disasm <program file> [--dot]";
+
+// Prints a disassembly listing, or with --dot, the program's control-flow
+// graph in Graphviz format.
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = args.next().expect(USAGE);
+    let dot = match args.next().as_deref() {
+        None => false,
+        Some("--dot") => true,
+        Some(_) => panic!("{}", USAGE),
+    };
+    let program = intcode::load_program(&path).expect("Couldn't read program");
+
+    if dot {
+        print!("{}", intcode::analyze(&program).to_dot());
+        return;
+    }
+    for line in intcode::disassemble(&program) {
+        println!("{}", line);
+    }
+}
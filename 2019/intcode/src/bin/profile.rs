@@ -0,0 +1,40 @@
+use intcode::{Io, Machine, Profiler};
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: profile <program file> [--coverage] [input...]";
+
+// Runs a program with the given inputs, prints its outputs, and then prints a
+// profile: instruction counts and hot loops, or with --coverage, the
+// disassembly annotated with how often each instruction ran.
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = args.next().expect(USAGE);
+    let mut coverage = false;
+    let mut inputs = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--coverage" => coverage = true,
+            _ => inputs.push(arg.parse::<i64>().expect(USAGE)),
+        }
+    }
+    let program = intcode::load_program(&path).expect("Couldn't read program");
+
+    let mut machine = Machine::new(program.clone());
+    let mut io = Io {
+        input: inputs.into_iter(),
+        output: vec![],
+    };
+    let mut profiler = Profiler::new();
+    let result = machine.run_device_traced(&mut io, &mut profiler);
+    println!("Output: {:?}", io.output);
+    if coverage {
+        print!("{}", profiler.coverage_report(&program));
+    } else {
+        print!("{}", profiler.summary());
+    }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
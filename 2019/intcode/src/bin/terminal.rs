@@ -0,0 +1,53 @@
+use intcode::{run_program, ErrorCause, Terminal};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::process;
+
+const USAGE: &str = "Usage: terminal <program file> [--replay transcript] [--record transcript]";
+
+// Runs an ASCII Intcode program on the terminal. Lines typed on stdin become
+// the program's input, and its output is printed as it runs.
+//
+// --replay feeds the lines of a transcript to the program before reading from
+// stdin. --record saves every line sent to the program, in the same format,
+// when the session ends.
+fn main() -> io::Result<()> {
+    let mut program_path = None;
+    let mut replay = None;
+    let mut record = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => replay = Some(args.next().expect(USAGE)),
+            "--record" => record = Some(args.next().expect(USAGE)),
+            _ if program_path.is_none() => program_path = Some(arg),
+            _ => panic!("{}", USAGE),
+        }
+    }
+    let program = intcode::load_program(program_path.expect(USAGE))?;
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut terminal = Terminal::new(stdin.lock(), stdout.lock());
+    if let Some(path) = replay {
+        terminal.replay(fs::read_to_string(path)?.lines());
+    }
+
+    let result = run_program(program, &mut terminal);
+    if let Some(path) = record {
+        terminal.write_transcript(&mut File::create(path)?)?;
+    }
+    terminal.finish()?.flush()?;
+    match result {
+        Ok(_) => Ok(()),
+        // The user ended the session.
+        Err(err) if err.cause == ErrorCause::MissingInput => Ok(()),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use super::disasm::decode_at;
+use super::{analyze, Block, Mode, Opcode};
+use super::{
+    ErrorCause, IntcodeError, IntcodeResult, Machine, Memory, RunResult, State, TraceStep,
+};
+use std::fmt::Write;
+use std::mem;
+
+// Translates `program` into the source of a Rust module, for a build script to
+// write into OUT_DIR and a crate to include!(). The module has:
+//
+//   pub const PROGRAM: &[i64];
+//   pub fn run(state: &mut dyn intcode::State) -> Result<IntcodeResult, IntcodeError>;
+//   pub fn run_input(input: &[i64]) -> Result<IntcodeResult, IntcodeError>;
+//
+// which behave like run_intcode() and run_intcode_input() on PROGRAM.
+//
+// Each basic block that analyze() finds becomes an arm of a `match` on the
+// instruction pointer. Anything the compiled code can't handle exactly hands
+// the run to an interpreter at the instruction that hit it: a jump to an
+// address that isn't the start of a block, an error or overflow, or a write
+// to a compiled instruction. A block that's been written to, by either the
+// compiled code or the interpreter, no longer matches memory, so it's
+// interpreted from then on. The interpreter hands the run back when it
+// reaches the start of a block that's still intact.
+//
+// The generated code isn't written to please clippy; put
+// #[allow(clippy::all)] on the module that includes it.
+pub fn compile_to_rust(program: &[i64]) -> String {
+    let cfg = analyze(program);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// Generated by intcode::compile_to_rust(). Don't edit."
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "pub const PROGRAM: &[i64] = &[").unwrap();
+    for chunk in program.chunks(16) {
+        let words: Vec<String> = chunk.iter().map(i64::to_string).collect();
+        writeln!(out, "    {},", words.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "// The words the compiled blocks were translated from."
+    )
+    .unwrap();
+    writeln!(out, "const CODE: &[(usize, usize)] = &[").unwrap();
+    for block in &cfg.blocks {
+        writeln!(out, "    ({}, {}),", block.start, block.end).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    out.push_str(
+        "
+pub fn run(
+    state: &mut dyn intcode::State,
+) -> Result<intcode::IntcodeResult, intcode::IntcodeError> {
+    intcode::CompiledRun::new(PROGRAM, CODE, state).run(execute)
+}
+
+pub fn run_input(input: &[i64]) -> Result<intcode::IntcodeResult, intcode::IntcodeError> {
+    run(&mut intcode::VecState::new(input.to_vec()))
+}
+
+#[allow(unreachable_code, unused_mut)]
+fn execute(rt: &mut intcode::CompiledRun, mut ip: usize) -> Result<(), usize> {
+    loop {
+        ip = match ip {
+",
+    );
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        compile_block(program, index, block, &mut out);
+    }
+    out.push_str(
+        "            _ => return Err(ip),
+        };
+    }
+}
+",
+    );
+    out
+}
+
+// Writes the match arm for `block`, which evaluates to the next ip.
+fn compile_block(program: &[i64], index: usize, block: &Block, out: &mut String) {
+    writeln!(
+        out,
+        "            {} if rt.intact({}) => {{",
+        block.start, index
+    )
+    .unwrap();
+    let indent = "                ";
+    let mut next = block.end;
+    for line in &block.lines {
+        writeln!(out, "{}// {}", indent, line).unwrap();
+        let ip = line.address;
+        next = ip + line.len;
+        let (opcode, modes, params) = match decode_at(program, ip) {
+            Some(decoded) => decoded,
+            None => {
+                // Let the interpreter report the error.
+                writeln!(out, "{}return Err({});", indent, ip).unwrap();
+                writeln!(out, "            }}").unwrap();
+                return;
+            }
+        };
+        let read = |i: usize| match modes[i] {
+            Mode::Position => format!("rt.position({}, {})?", ip, params[i]),
+            Mode::Immediate => params[i].to_string(),
+            Mode::Relative => format!("rt.relative({}, {})?", ip, params[i]),
+        };
+        let store = |i: usize, val: &str| {
+            let addr = match modes[i] {
+                Mode::Relative => format!("rt.relative_address({}, {})?", ip, params[i]),
+                _ => format!("rt.address({}, {})?", ip, params[i]),
+            };
+            // The address comes first, so an input isn't consumed by an
+            // instruction that can't store it.
+            format!(
+                "{}let addr = {};\n{}let val = {};\n{}rt.store({}, addr, val)?;",
+                indent, addr, indent, val, indent, next
+            )
+        };
+        let code = match opcode {
+            Opcode::Add => store(2, &format!("rt.add({}, {}, {})?", ip, read(0), read(1))),
+            Opcode::Mul => store(2, &format!("rt.mul({}, {}, {})?", ip, read(0), read(1))),
+            Opcode::In => store(0, &format!("rt.input({})?", ip)),
+            Opcode::Out => format!("{}rt.output({});", indent, read(0)),
+            Opcode::LessThan => store(2, &format!("i64::from({} < {})", read(0), read(1))),
+            Opcode::Equals => store(2, &format!("i64::from({} == {})", read(0), read(1))),
+            Opcode::AdjustRelativeBase => {
+                format!("{}rt.adjust_relative_base({}, {})?;", indent, ip, read(0))
+            }
+            // These end the block.
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let test = if opcode == Opcode::JumpIfTrue {
+                    "!="
+                } else {
+                    "=="
+                };
+                writeln!(out, "{}if {} {} 0 {{", indent, read(0), test).unwrap();
+                writeln!(out, "{}    rt.jump({}, {})?", indent, ip, read(1)).unwrap();
+                writeln!(out, "{}}} else {{", indent).unwrap();
+                writeln!(out, "{}    {}", indent, next).unwrap();
+                writeln!(out, "{}}}", indent).unwrap();
+                writeln!(out, "            }}").unwrap();
+                return;
+            }
+            Opcode::Halt => {
+                writeln!(out, "{}return Ok(());", indent).unwrap();
+                writeln!(out, "            }}").unwrap();
+                return;
+            }
+            Opcode::Custom(_) => unreachable!("decode_at() only returns standard opcodes"),
+        };
+        writeln!(out, "{}", code).unwrap();
+    }
+    writeln!(out, "{}{}", indent, next).unwrap();
+    writeln!(out, "            }}").unwrap();
+}
+
+// The state of a run of a program compiled by compile_to_rust(). The
+// generated code calls these methods; they aren't useful on their own.
+//
+// Methods that can fail return the address to continue from in the
+// interpreter: the current instruction if it didn't have any effect yet, or
+// the next one if it did.
+pub struct CompiledRun<'a> {
+    memory: Vec<i64>,
+    relative_base: i64,
+    state: &'a mut dyn State,
+    // The start and end of each compiled block.
+    blocks: &'a [(usize, usize)],
+    // The index of the block each compiled word belongs to, by address.
+    block_of: Vec<Option<usize>>,
+    // Whether each block's words are still the ones it was compiled from.
+    intact: Vec<bool>,
+}
+
+impl<'a> CompiledRun<'a> {
+    pub fn new(
+        program: &[i64],
+        blocks: &'a [(usize, usize)],
+        state: &'a mut dyn State,
+    ) -> CompiledRun<'a> {
+        let mut block_of = vec![None; program.len()];
+        for (index, &(start, end)) in blocks.iter().enumerate() {
+            for entry in &mut block_of[start..end] {
+                *entry = Some(index);
+            }
+        }
+        CompiledRun {
+            memory: program.to_vec(),
+            relative_base: 0,
+            state,
+            blocks,
+            block_of,
+            intact: vec![true; blocks.len()],
+        }
+    }
+
+    // Runs the generated code, switching to the interpreter and back as
+    // needed.
+    pub fn run(
+        mut self,
+        execute: fn(&mut CompiledRun, usize) -> Result<(), usize>,
+    ) -> Result<IntcodeResult, IntcodeError> {
+        let mut ip = 0;
+        while let Err(fallback) = execute(&mut self, ip) {
+            match self.interpret(fallback)? {
+                Some(resume) => ip = resume,
+                None => break,
+            }
+        }
+        Ok(IntcodeResult {
+            memory: self.memory,
+            output: self.state.copy_output(),
+        })
+    }
+
+    // Interprets from `ip` until the program halts, returning None, or
+    // reaches an intact block, returning its address.
+    fn interpret(&mut self, ip: usize) -> Result<Option<usize>, IntcodeError> {
+        let mut machine = Machine::new(mem::take(&mut self.memory));
+        machine.set_ip(ip);
+        machine.set_relative_base(self.relative_base);
+        let resume = loop {
+            let mut written = None;
+            let result = machine.step_traced(&mut |step: &TraceStep| {
+                written = step.write.map(|(addr, _)| addr);
+            })?;
+            if let Some(addr) = written {
+                invalidate(&self.block_of, &mut self.intact, addr);
+            }
+            match result {
+                Some(RunResult::NeedsInput) => match self.state.input() {
+                    Some(val) => {
+                        machine.push_input(val);
+                        continue;
+                    }
+                    None => return Err(machine.error(ErrorCause::MissingInput)),
+                },
+                Some(RunResult::Output(val)) => self.state.output(val),
+                Some(RunResult::Halted) => break None,
+                Some(RunResult::BudgetExhausted) | None => {}
+            }
+            let ip = machine.ip();
+            if let Some(&Some(index)) = self.block_of.get(ip) {
+                if self.blocks[index].0 == ip && self.intact[index] {
+                    break Some(ip);
+                }
+            }
+        };
+        self.relative_base = machine.relative_base();
+        self.memory = machine.into_memory();
+        Ok(resume)
+    }
+
+    #[inline]
+    pub fn intact(&self, block: usize) -> bool {
+        self.intact[block]
+    }
+
+    #[inline]
+    pub fn address(&self, ip: usize, addr: i64) -> Result<usize, usize> {
+        // The interpreter reports addresses it can't use, including ones past
+        // the default limit.
+        if addr < 0 || addr as u64 > <Vec<i64>>::DEFAULT_MAX_ADDRESS as u64 {
+            return Err(ip);
+        }
+        Ok(addr as usize)
+    }
+
+    #[inline]
+    pub fn relative_address(&self, ip: usize, offset: i64) -> Result<usize, usize> {
+        self.address(ip, self.relative_base.checked_add(offset).ok_or(ip)?)
+    }
+
+    #[inline]
+    pub fn position(&self, ip: usize, addr: i64) -> Result<i64, usize> {
+        let addr = self.address(ip, addr)?;
+        Ok(self.memory.get(addr).copied().unwrap_or(0))
+    }
+
+    #[inline]
+    pub fn relative(&self, ip: usize, offset: i64) -> Result<i64, usize> {
+        self.position(ip, self.relative_base.checked_add(offset).ok_or(ip)?)
+    }
+
+    #[inline]
+    pub fn add(&self, ip: usize, a: i64, b: i64) -> Result<i64, usize> {
+        a.checked_add(b).ok_or(ip)
+    }
+
+    #[inline]
+    pub fn mul(&self, ip: usize, a: i64, b: i64) -> Result<i64, usize> {
+        a.checked_mul(b).ok_or(ip)
+    }
+
+    // Gives up after writing to compiled code, at `next`.
+    #[inline]
+    pub fn store(&mut self, next: usize, addr: usize, val: i64) -> Result<(), usize> {
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, 0);
+        }
+        self.memory[addr] = val;
+        if invalidate(&self.block_of, &mut self.intact, addr) {
+            return Err(next);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn input(&mut self, ip: usize) -> Result<i64, usize> {
+        self.state.input().ok_or(ip)
+    }
+
+    #[inline]
+    pub fn output(&mut self, val: i64) {
+        self.state.output(val)
+    }
+
+    #[inline]
+    pub fn adjust_relative_base(&mut self, ip: usize, adjust: i64) -> Result<(), usize> {
+        self.relative_base = self.relative_base.checked_add(adjust).ok_or(ip)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn jump(&self, ip: usize, target: i64) -> Result<usize, usize> {
+        self.address(ip, target)
+    }
+}
+
+// Marks the block containing `addr` as modified, if there is one, and returns
+// whether there was.
+fn invalidate(block_of: &[Option<usize>], intact: &mut [bool], addr: usize) -> bool {
+    match block_of.get(addr) {
+        Some(&Some(index)) => {
+            intact[index] = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_source() {
+        let source = compile_to_rust(&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert!(source.contains(
+            "pub const PROGRAM: &[i64] = &[\n    3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8,\n];"
+        ));
+        assert!(source.contains("const CODE: &[(usize, usize)] = &[\n    (0, 9),\n];"));
+        assert!(source.contains(
+            "            0 if rt.intact(0) => {
+                // 0000: IN -> [9]
+                let addr = rt.address(0, 9)?;
+                let val = rt.input(0)?;
+                rt.store(2, addr, val)?;
+                // 0002: EQ [9], [10] -> [9]
+                let addr = rt.address(2, 9)?;
+                let val = i64::from(rt.position(2, 9)? == rt.position(2, 10)?);
+"
+        ));
+        assert!(source.contains(
+            "                // 0008: HLT
+                return Ok(());
+            }
+            _ => return Err(ip),"
+        ));
+    }
+}
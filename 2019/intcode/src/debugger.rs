@@ -0,0 +1,475 @@
+use super::{disassemble, disassemble_at, Machine, RunResult, TraceStep};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+const HELP: &str = "\
+break|b ADDR       stop before executing ADDR
+delete|d ADDR      remove a breakpoint
+watch|w ADDR       stop after any write to memory cell ADDR
+unwatch ADDR       remove a watchpoint
+step|s [N]         execute N instructions (default 1)
+continue|c [N]     run until a breakpoint, watchpoint, input wait, or halt;
+                   the instruction at the ip always runs, so continuing from
+                   a breakpoint doesn't stop at it again. Gives up after N
+                   instructions (default 1000000), so `c` again to go on
+back|bs [N]        undo the last N instructions (default 1)
+who ADDR           go back to the last instruction that wrote ADDR
+input|i VALUE...   queue input values
+mem|x ADDR [N]     show N memory cells starting at ADDR (default 1); N is
+                   capped at the size of memory
+set ADDR VALUE     change a memory cell
+rb [VALUE]         show or change the relative base
+list|l [ADDR]      disassemble around ADDR (default the instruction pointer)
+info               show registers, pending input, breakpoints, and watchpoints
+quit|q             end the session
+";
+
+// How many instructions `back` and `who` can undo.
+const HISTORY_LIMIT: usize = 1_000_000;
+
+// How many instructions `continue` runs before returning to the prompt, so a
+// program that never stops doesn't hang the session.
+const CONTINUE_LIMIT: u64 = 1_000_000;
+
+// A command-driven debugger around a Machine. Each command writes its results
+// to an io::Write, so sessions can be scripted and their transcripts compared.
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    stopped: Option<RunResult>,
+}
+
+impl Debugger {
+    pub fn new(program: Vec<i64>) -> Debugger {
+        let mut machine = Machine::new(program);
+        machine.set_history(Some(HISTORY_LIMIT));
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            stopped: None,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    // Runs one command line. Returns Ok(false) once the user asks to quit.
+    pub fn command(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+        match self.dispatch(command, args, out) {
+            Ok(keep_going) => Ok(keep_going),
+            Err(CommandError::Io(err)) => Err(err),
+            Err(CommandError::Usage(message)) => {
+                writeln!(out, "error: {}", message)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        out: &mut dyn Write,
+    ) -> Result<bool, CommandError> {
+        match command {
+            "break" | "b" => {
+                self.breakpoints.insert(arg(args, 0)?);
+            }
+            "delete" | "d" => {
+                self.breakpoints.remove(&arg(args, 0)?);
+            }
+            "watch" | "w" => {
+                self.watchpoints.insert(arg(args, 0)?);
+            }
+            "unwatch" => {
+                self.watchpoints.remove(&arg(args, 0)?);
+            }
+            "step" | "s" => {
+                let count = optional_arg(args, 0)?.unwrap_or(1);
+                for _ in 0..count {
+                    if self.step(out)? {
+                        break;
+                    }
+                }
+                self.show_ip(out)?;
+            }
+            "continue" | "c" => {
+                // Breakpoints are checked after each step, like a debugger
+                // resuming from one, so the instruction at ip always runs.
+                let limit = optional_arg(args, 0)?.unwrap_or(CONTINUE_LIMIT);
+                let mut executed = 0;
+                while !self.step(out)? {
+                    executed += 1;
+                    if self.breakpoints.contains(&self.machine.ip()) {
+                        writeln!(out, "breakpoint at {}", self.machine.ip())?;
+                        break;
+                    }
+                    if executed >= limit {
+                        writeln!(out, "still running after {} instructions", executed)?;
+                        break;
+                    }
+                }
+                self.show_ip(out)?;
+            }
+            "back" | "bs" => {
+                let count = optional_arg(args, 0)?.unwrap_or(1);
+                for _ in 0..count {
+                    if !self.machine.step_back() {
+                        writeln!(out, "no more history")?;
+                        break;
+                    }
+                    self.stopped = None;
+                }
+                self.show_ip(out)?;
+            }
+            "who" => {
+                let addr = arg(args, 0)?;
+                if self.machine.run_back_to_write(addr) {
+                    self.stopped = None;
+                    self.show_ip(out)?;
+                } else {
+                    writeln!(out, "no write to {} in history", addr)?;
+                }
+            }
+            "input" | "i" => {
+                for i in 0..args.len() {
+                    self.machine.push_input(arg(args, i)?);
+                }
+                if let Some(RunResult::NeedsInput) = self.stopped {
+                    self.stopped = None;
+                }
+            }
+            "mem" | "x" => {
+                let start: usize = arg(args, 0)?;
+                let count = optional_arg(args, 1)?
+                    .unwrap_or(1)
+                    .min(self.machine.memory().len());
+                let end = start.checked_add(count).ok_or_else(|| {
+                    CommandError::Usage(format!(
+                        "{} cells from {} is past the end of memory",
+                        count, start
+                    ))
+                })?;
+                for addr in start..end {
+                    writeln!(out, "{:04}: {}", addr, self.machine.read(addr))?;
+                }
+            }
+            "set" => {
+                self.machine
+                    .write(arg(args, 0)?, arg(args, 1)?)
+                    .map_err(|cause| CommandError::Usage(cause.to_string()))?;
+            }
+            "rb" => match optional_arg(args, 0)? {
+                Some(rb) => self.machine.set_relative_base(rb),
+                None => writeln!(out, "rb = {}", self.machine.relative_base())?,
+            },
+            "list" | "l" => {
+                let addr = optional_arg(args, 0)?.unwrap_or_else(|| self.machine.ip());
+                self.list(addr, out)?;
+            }
+            "info" => {
+                writeln!(
+                    out,
+                    "ip = {}, rb = {}",
+                    self.machine.ip(),
+                    self.machine.relative_base()
+                )?;
+                writeln!(
+                    out,
+                    "input: {:?}",
+                    self.machine.pending_input().collect::<Vec<_>>()
+                )?;
+                writeln!(out, "breakpoints: {:?}", self.breakpoints)?;
+                writeln!(out, "watchpoints: {:?}", self.watchpoints)?;
+            }
+            "help" | "h" | "?" => write!(out, "{}", HELP)?,
+            "quit" | "q" => return Ok(false),
+            _ => {
+                return Err(CommandError::Usage(format!(
+                    "unknown command `{}`; try `help`",
+                    command
+                )))
+            }
+        }
+        Ok(true)
+    }
+
+    // Executes one instruction, reporting anything interesting. Returns true if
+    // execution should stop.
+    fn step(&mut self, out: &mut dyn Write) -> Result<bool, CommandError> {
+        match self.stopped {
+            Some(RunResult::Halted) => {
+                writeln!(out, "halted")?;
+                return Ok(true);
+            }
+            Some(RunResult::NeedsInput) => {
+                writeln!(out, "waiting for input")?;
+                return Ok(true);
+            }
+            _ => {}
+        }
+        let old_values: BTreeMap<usize, i64> = self
+            .watchpoints
+            .iter()
+            .map(|&addr| (addr, self.machine.read(addr)))
+            .collect();
+        let mut written = None;
+        let result = self
+            .machine
+            .step_traced(&mut |step: &TraceStep| written = step.write);
+        let mut stop = false;
+        match result {
+            Err(err) => {
+                writeln!(out, "error: {}", err)?;
+                return Ok(true);
+            }
+            Ok(Some(RunResult::Output(val))) => writeln!(out, "output: {}", val)?,
+            Ok(Some(stopped)) => {
+                self.stopped = Some(stopped);
+                return self.step(out);
+            }
+            Ok(None) => {}
+        }
+        if let Some((addr, new_value)) = written {
+            if let Some(old_value) = old_values.get(&addr) {
+                writeln!(out, "watchpoint {}: {} -> {}", addr, old_value, new_value)?;
+                stop = true;
+            }
+        }
+        Ok(stop)
+    }
+
+    fn show_ip(&self, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(line) = disassemble_at(self.machine.memory(), self.machine.ip()) {
+            writeln!(out, "> {}", line)?;
+        }
+        Ok(())
+    }
+
+    // Shows a few instructions before and after `addr`. The ones before come
+    // from disassembling the whole program, so they may not line up with how
+    // execution actually reaches `addr`.
+    fn list(&self, addr: usize, out: &mut dyn Write) -> io::Result<()> {
+        const BEFORE: usize = 3;
+        const AFTER: usize = 5;
+        let memory = self.machine.memory();
+        let before: Vec<_> = disassemble(&memory[..addr.min(memory.len())])
+            .into_iter()
+            .filter(|line| line.address + line.len <= addr)
+            .collect();
+        for line in &before[before.len().saturating_sub(BEFORE)..] {
+            writeln!(out, "  {}", line)?;
+        }
+        let mut next = addr;
+        for _ in 0..AFTER {
+            let line = match disassemble_at(memory, next) {
+                Some(line) => line,
+                None => break,
+            };
+            let marker = if next == self.machine.ip() {
+                '>'
+            } else if self.breakpoints.contains(&next) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(out, "{} {}", marker, line)?;
+            next += line.len;
+        }
+        Ok(())
+    }
+}
+
+enum CommandError {
+    Usage(String),
+    Io(io::Error),
+}
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> CommandError {
+        CommandError::Io(err)
+    }
+}
+
+fn arg<T: FromStr>(args: &[&str], i: usize) -> Result<T, CommandError> {
+    optional_arg(args, i)?.ok_or_else(|| CommandError::Usage(format!("missing argument {}", i + 1)))
+}
+
+fn optional_arg<T: FromStr>(args: &[&str], i: usize) -> Result<Option<T>, CommandError> {
+    match args.get(i) {
+        None => Ok(None),
+        Some(text) => text
+            .parse()
+            .map(Some)
+            .map_err(|_| CommandError::Usage(format!("invalid number `{}`", text))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn session(program: Vec<i64>, script: &str) -> String {
+        let mut debugger = Debugger::new(program);
+        let mut out = vec![];
+        for line in script.lines() {
+            if !debugger.command(line, &mut out).unwrap() {
+                break;
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn breakpoints_and_input() {
+        let program = assemble(
+            "      in -> [x]
+                   mul [x], #2 -> [x]
+                   out [x]
+                   hlt
+                x: data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            session(
+                program,
+                "b 6
+                 c
+                 i 21
+                 c
+                 x 9
+                 c
+                 s
+                 q
+                 s"
+            ),
+            "waiting for input
+> 0000: IN -> [9]
+breakpoint at 6
+> 0006: OUT [9]
+0009: 42
+output: 42
+halted
+> 0008: HLT
+halted
+> 0008: HLT
+"
+        );
+    }
+
+    #[test]
+    fn reverse_stepping() {
+        let program = assemble(
+            "      in -> [x]
+                   mul [x], #2 -> [x]
+                   add [x], #1 -> [y]
+                   out [y]
+                   hlt
+                x: data 0
+                y: data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            session(
+                program,
+                "i 5
+                 c
+                 who 14
+                 who 13
+                 who 100
+                 back
+                 x 13
+                 back 5
+                 c"
+            ),
+            "output: 11
+halted
+> 0012: HLT
+> 0006: ADD [13], #1 -> [14]
+> 0002: MUL [13], #2 -> [13]
+no write to 100 in history
+> 0000: IN -> [13]
+0013: 0
+no more history
+> 0000: IN -> [13]
+output: 11
+halted
+> 0012: HLT
+"
+        );
+    }
+
+    #[test]
+    fn watchpoints_and_registers() {
+        let program = assemble(
+            "      arb #5
+                   add #1, #2 -> [rb+2]
+                   add #3, #4 -> [rb+3]
+                   hlt",
+        )
+        .unwrap();
+        assert_eq!(
+            session(
+                program,
+                "w 8
+                 c
+                 rb
+                 rb 100
+                 set 7 -1
+                 x 7 2
+                 l 2
+                 x 18446744073709551615 2
+                 set 16777216 1
+                 x 9 18446744073709551615
+                 bogus"
+            ),
+            "watchpoint 8: 4 -> 7
+> 0010: HLT
+rb = 5
+0007: -1
+0008: 7
+  0000: ARB #5
+  0002: ADD #1, #2 -> [rb+2]
+  0006: ADD #-1, #7 -> [rb+3]
+> 0010: HLT
+error: 2 cells from 18446744073709551615 is past the end of memory
+error: address 16777216 out of range
+0009: 3
+0010: 99
+0011: 0
+0012: 0
+0013: 0
+0014: 0
+0015: 0
+0016: 0
+0017: 0
+0018: 0
+0019: 0
+error: unknown command `bogus`; try `help`
+"
+        );
+    }
+
+    #[test]
+    fn continue_gives_up() {
+        let program = assemble("loop: jt #1, #loop").unwrap();
+        assert_eq!(
+            session(program, "c 10\nc 5"),
+            "still running after 10 instructions
+> 0000: JT #1, #0
+still running after 5 instructions
+> 0000: JT #1, #0
+"
+        );
+    }
+}
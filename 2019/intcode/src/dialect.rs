@@ -0,0 +1,103 @@
+use super::{InstructionSet, Machine, Mode, Opcode};
+
+// The versions of Intcode the puzzles introduced one at a time. Each day's
+// programs only use its own dialect, and running them in an older one fails
+// with UnknownOpcode or UnknownMode instead of misbehaving.
+//
+// The earlier dialects also match the original interpreters in not letting a
+// program touch memory outside its image. Unlike those interpreters, values
+// are i64 rather than i32, which only matters to programs that overflow.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Dialect {
+    // Add, multiply, and halt, with every parameter in position mode.
+    Day2,
+    // Adds input, output, jumps, comparisons, and immediate mode.
+    Day5,
+    // Adds relative mode and the relative base, and memory beyond the
+    // program. This is InstructionSet::standard().
+    Day9,
+}
+
+impl Dialect {
+    pub fn instruction_set(&self) -> InstructionSet {
+        let mut set = InstructionSet::standard();
+        match self {
+            Dialect::Day2 => {
+                set.retain(|op| matches!(op, Opcode::Add | Opcode::Mul | Opcode::Halt));
+                set.set_modes(&[Mode::Position]);
+            }
+            Dialect::Day5 => {
+                set.remove(Opcode::AdjustRelativeBase.code());
+                set.set_modes(&[Mode::Position, Mode::Immediate]);
+            }
+            Dialect::Day9 => {}
+        }
+        set
+    }
+}
+
+impl Machine {
+    pub fn with_dialect(program: Vec<i64>, dialect: Dialect) -> Machine {
+        let len = program.len();
+        let mut machine = Machine::new(program);
+        machine.set_instruction_set(dialect.instruction_set());
+        if dialect != Dialect::Day9 {
+            machine.set_max_address(len.saturating_sub(1));
+        }
+        machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorCause, RunResult};
+
+    fn run(program: Vec<i64>, dialect: Dialect) -> Result<Vec<i64>, ErrorCause> {
+        let mut machine = Machine::with_dialect(program, dialect);
+        machine.push_input(7);
+        let mut outputs = vec![];
+        loop {
+            match machine.run().map_err(|err| err.cause)? {
+                RunResult::Output(val) => outputs.push(val),
+                RunResult::Halted => return Ok(outputs),
+                result => panic!("{:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn each_dialect_rejects_later_features() {
+        // Immediate mode.
+        let immediate = vec![1101, 2, 3, 0, 4, 0, 99];
+        assert_eq!(
+            run(immediate.clone(), Dialect::Day2),
+            Err(ErrorCause::UnknownMode(1))
+        );
+        assert_eq!(run(immediate, Dialect::Day5), Ok(vec![5]));
+        // Input and output.
+        let echo = vec![3, 0, 4, 0, 99];
+        assert_eq!(
+            run(echo.clone(), Dialect::Day2),
+            Err(ErrorCause::UnknownOpcode(3))
+        );
+        assert_eq!(run(echo, Dialect::Day5), Ok(vec![7]));
+        // The relative base.
+        let relative = vec![109, 7, 204, -1, 99, 0, 42, 0];
+        assert_eq!(
+            run(relative.clone(), Dialect::Day5),
+            Err(ErrorCause::UnknownOpcode(9))
+        );
+        assert_eq!(run(relative, Dialect::Day9), Ok(vec![42]));
+    }
+
+    #[test]
+    fn memory_is_limited_to_the_program() {
+        let far_write = vec![1101, 1, 1, 100, 4, 100, 99];
+        assert_eq!(
+            run(far_write.clone(), Dialect::Day5),
+            Err(ErrorCause::AddressOutOfRange(100))
+        );
+        assert_eq!(run(far_write, Dialect::Day9), Ok(vec![2]));
+    }
+}
@@ -0,0 +1,148 @@
+use super::{decode, Mode, Opcode};
+use std::fmt;
+
+// One line of a disassembly listing: either a decoded instruction or a single
+// word that doesn't decode and is assumed to be data.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Line {
+    pub address: usize,
+    // How many words of memory this line covers.
+    pub len: usize,
+    pub text: String,
+}
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: {}", self.address, self.text)
+    }
+}
+
+// Disassembles the single instruction at `address`, or marks it as data if it
+// isn't a valid instruction. Returns None if `address` is past the end of
+// memory.
+pub fn disassemble_at(memory: &[i64], address: usize) -> Option<Line> {
+    let word = *memory.get(address)?;
+    Some(match instruction_text(memory, address) {
+        Some((len, text)) => Line { address, len, text },
+        None => Line {
+            address,
+            len: 1,
+            text: format!("DATA {}", word),
+        },
+    })
+}
+
+// Walks memory from address 0, decoding one instruction after another.
+// Because Intcode doesn't distinguish code from data, anything that happens to
+// decode is shown as an instruction.
+pub fn disassemble(memory: &[i64]) -> Vec<Line> {
+    let mut result = vec![];
+    let mut address = 0;
+    while let Some(line) = disassemble_at(memory, address) {
+        address += line.len;
+        result.push(line);
+    }
+    result
+}
+
+// Decodes the instruction at `address` if it's one that could run: a valid
+// opcode and modes, all its parameters inside `memory`, and no immediate-mode
+// write.
+pub(crate) fn decode_at(memory: &[i64], address: usize) -> Option<(Opcode, [Mode; 3], &[i64])> {
+    let (opcode, modes) = decode(*memory.get(address)?).ok()?;
+    let params = memory.get(address + 1..address + 1 + opcode.params())?;
+    if let Some(w) = opcode.write_param() {
+        if let Mode::Immediate = modes[w] {
+            return None;
+        }
+    }
+    Some((opcode, modes, params))
+}
+
+fn instruction_text(memory: &[i64], address: usize) -> Option<(usize, String)> {
+    let (opcode, modes, params) = decode_at(memory, address)?;
+    let write_param = opcode.write_param();
+    let mut text = opcode.mnemonic().to_string();
+    let reads: Vec<String> = (0..opcode.params())
+        .filter(|&i| Some(i) != write_param)
+        .map(|i| operand(modes[i], params[i]))
+        .collect();
+    if !reads.is_empty() {
+        text.push(' ');
+        text.push_str(&reads.join(", "));
+    }
+    if let Some(w) = write_param {
+        text.push_str(" -> ");
+        text.push_str(&operand(modes[w], params[w]));
+    }
+    Some((opcode.params() + 1, text))
+}
+
+pub(crate) fn operand(mode: Mode, param: i64) -> String {
+    match mode {
+        Mode::Position => format!("[{}]", param),
+        Mode::Immediate => format!("#{}", param),
+        Mode::Relative if param < 0 => format!("[rb-{}]", -param),
+        Mode::Relative => format!("[rb+{}]", param),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(memory: &[i64]) -> Vec<String> {
+        disassemble(memory).iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn modes_and_write_targets() {
+        assert_eq!(
+            listing(&[22201, 3, 5, 100, 99]),
+            vec!["0000: ADD [rb+3], [rb+5] -> [rb+100]", "0004: HLT"]
+        );
+        assert_eq!(
+            listing(&[1001, 3, 5, 100]),
+            vec!["0000: ADD [3], #5 -> [100]"]
+        );
+        assert_eq!(
+            listing(&[3, 9, 204, -1, 109, 4, 1105, 1, 9]),
+            vec![
+                "0000: IN -> [9]",
+                "0002: OUT [rb-1]",
+                "0004: ARB #4",
+                "0006: JT #1, #9"
+            ]
+        );
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(
+            listing(&[4, 3, 99, 42, 11101, 0, 0, 0]),
+            vec![
+                "0000: OUT [3]",
+                "0002: HLT",
+                "0003: DATA 42",
+                "0004: DATA 11101",
+                "0005: DATA 0",
+                "0006: DATA 0",
+                "0007: DATA 0",
+            ]
+        );
+        // Truncated instructions are data too.
+        assert_eq!(
+            listing(&[1, 0, 0]),
+            vec!["0000: DATA 1", "0001: DATA 0", "0002: DATA 0"]
+        );
+    }
+
+    #[test]
+    fn past_the_end() {
+        assert_eq!(disassemble_at(&[1, 0, 0], 3), None);
+        assert_eq!(disassemble_at(&[], usize::MAX), None);
+        assert_eq!(
+            disassemble_at(&[1, 0, 0], 2).map(|l| l.to_string()),
+            Some("0002: DATA 0".to_string())
+        );
+    }
+}
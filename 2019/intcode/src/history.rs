@@ -0,0 +1,222 @@
+use super::{Machine, Memory};
+use std::collections::VecDeque;
+
+// What's needed to undo one instruction.
+#[derive(Clone, Copy)]
+struct Undo {
+    ip: usize,
+    relative_base: i64,
+    // The address the instruction wrote, and the value that was there before.
+    write: Option<(usize, i64)>,
+    // The input the instruction consumed.
+    input: Option<i64>,
+    // The length of memory before the instruction, which a write past the
+    // end grows.
+    len: usize,
+}
+
+// An undo log of the most recent instructions a machine executed, so it can
+// step backwards. Outputs can't be taken back, so stepping back over an
+// output instruction just means it will be produced again.
+#[derive(Clone)]
+pub(crate) struct History {
+    undo: VecDeque<Undo>,
+    limit: usize,
+    // The write made by the instruction that's executing, if any.
+    pending_write: Option<(usize, i64)>,
+}
+impl History {
+    fn new(limit: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            limit,
+            pending_write: None,
+        }
+    }
+    pub(crate) fn store(&mut self, addr: usize, old: i64) {
+        self.pending_write = Some((addr, old));
+    }
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.pending_write = None;
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    // Keeps an undo log of the last `limit` instructions so the machine can
+    // step backwards, or turns it off with None. It's off by default.
+    pub fn set_history(&mut self, limit: Option<usize>) {
+        self.history = limit.map(History::new);
+    }
+
+    // How many instructions step_back() can currently undo.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.undo.len())
+    }
+
+    // Undoes the last instruction: its memory write, its input, and its
+    // changes to the instruction pointer and relative base. Returns false if
+    // there's no history left.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|h| h.undo.pop_back()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        if let Some((addr, old)) = undo.write {
+            // The instruction managed to write here, so this can't fail.
+            self.set_cell(addr, old).expect("undo write failed");
+        }
+        self.memory.truncate(undo.len);
+        if let Some(val) = undo.input {
+            self.input.push_front(val);
+        }
+        self.ip = undo.ip;
+        self.relative_base = undo.relative_base;
+        self.reset_loop_detector();
+        self.executed -= 1;
+        true
+    }
+
+    // Steps back to just before the most recent instruction that wrote to
+    // `addr`, so ip() is that instruction. Returns false, without moving, if
+    // no instruction in the history wrote to `addr`.
+    pub fn run_back_to_write(&mut self, addr: usize) -> bool {
+        let steps = match &self.history {
+            Some(history) => history
+                .undo
+                .iter()
+                .rev()
+                .position(|u| matches!(u.write, Some((a, _)) if a == addr)),
+            None => None,
+        };
+        match steps {
+            Some(steps) => {
+                for _ in 0..=steps {
+                    self.step_back();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Called after each attempt to execute an instruction, with the state from
+    // before it.
+    pub(crate) fn record_history(
+        &mut self,
+        ip: usize,
+        relative_base: i64,
+        executed: u64,
+        pending_input: usize,
+        len: usize,
+    ) {
+        let write = match &mut self.history {
+            Some(history) => history.pending_write.take(),
+            None => return,
+        };
+        if self.executed == executed {
+            // The instruction failed or is waiting for input.
+            return;
+        }
+        // An input instruction's input is the value it wrote.
+        let input = if self.input.len() < pending_input {
+            write.map(|(addr, _)| self.memory.read(addr))
+        } else {
+            None
+        };
+        let history = self.history.as_mut().unwrap();
+        history.undo.push_back(Undo {
+            ip,
+            relative_base,
+            write,
+            input,
+            len,
+        });
+        while history.undo.len() > history.limit {
+            history.undo.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, Machine, RunResult};
+
+    fn program() -> Vec<i64> {
+        assemble(
+            "      in -> [x]
+                   arb #7
+                   mul [x], #3 -> [y]
+                   add [y], #1 -> [x]
+                   out [x]
+                   hlt
+            x:     data 0
+            y:     data 0",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn step_back_undoes_everything() {
+        let mut machine = Machine::new(program());
+        machine.set_history(Some(100));
+        machine.push_input(4);
+        let start = machine.snapshot();
+        assert_eq!(machine.run().unwrap(), RunResult::Output(13));
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+        assert_eq!(machine.history_len(), 6);
+        while machine.step_back() {}
+        assert_eq!(machine.snapshot(), start);
+        assert_eq!(machine.instructions_executed(), 0);
+        // And it runs the same way again.
+        assert_eq!(machine.run().unwrap(), RunResult::Output(13));
+    }
+
+    #[test]
+    fn who_wrote_it() {
+        let mut machine = Machine::new(program());
+        machine.set_history(Some(100));
+        machine.push_input(4);
+        machine.run().unwrap();
+        assert!(!machine.run_back_to_write(100));
+        assert_eq!(machine.ip(), 14);
+        assert!(machine.run_back_to_write(15));
+        assert_eq!(machine.ip(), 8);
+        assert_eq!(machine.read(15), 4);
+        assert!(machine.run_back_to_write(15));
+        assert_eq!(machine.ip(), 0);
+        assert_eq!(machine.pending_input().collect::<Vec<_>>(), vec![&4]);
+    }
+
+    #[test]
+    fn limit() {
+        let mut machine = Machine::new(program());
+        machine.set_history(Some(2));
+        machine.push_input(4);
+        machine.run().unwrap();
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert!(!machine.step_back());
+        assert_eq!(machine.ip(), 8);
+
+        let mut machine = Machine::new(program());
+        machine.set_history(Some(0));
+        machine.push_input(4);
+        machine.run().unwrap();
+        assert_eq!(machine.history_len(), 0);
+        assert!(!machine.step_back());
+    }
+
+    #[test]
+    fn step_back_shrinks_memory() {
+        let program = vec![1101, 1, 2, 100, 99];
+        let mut machine = Machine::new(program.clone());
+        machine.set_history(Some(10));
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+        assert_eq!(machine.memory().len(), 101);
+        // Back over the HLT, then the ADD.
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert_eq!(machine.memory(), &program);
+    }
+}
@@ -0,0 +1,199 @@
+use super::{Machine, Memory, Mode, Opcode};
+use std::fmt;
+
+// What a custom opcode's instruction does once its operands are loaded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Effect {
+    Continue,
+    // Stores a value through the opcode's write parameter.
+    Store(i64),
+    JumpTo(i64),
+    Output(i64),
+    Halt,
+}
+
+// An opcode defined outside this crate. `execute` gets the instruction's
+// operands the way a Tracer sees them: the value each parameter reads, or for
+// the write parameter, the address it writes to.
+//
+// Definitions are compared by identity, so each one should be a `static`.
+pub struct CustomOpcode {
+    pub code: i64,
+    pub mnemonic: &'static str,
+    // At most 3.
+    pub params: usize,
+    pub write_param: Option<usize>,
+    pub execute: fn(&[i64]) -> Effect,
+}
+impl PartialEq for CustomOpcode {
+    fn eq(&self, other: &CustomOpcode) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+impl Eq for CustomOpcode {}
+impl fmt::Debug for CustomOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CustomOpcode({} {})", self.code, self.mnemonic)
+    }
+}
+
+// The opcodes a Machine understands, by their two-digit code, and the
+// parameter modes it accepts. Anything else fails with
+// ErrorCause::UnknownOpcode or ErrorCause::UnknownMode.
+#[derive(Clone)]
+pub struct InstructionSet {
+    table: [Option<Opcode>; 100],
+    // Indexed by mode code.
+    modes: [bool; 3],
+}
+
+impl InstructionSet {
+    // No opcodes, and all modes.
+    pub fn empty() -> InstructionSet {
+        InstructionSet {
+            table: [None; 100],
+            modes: [true; 3],
+        }
+    }
+
+    // Every opcode from days 2, 5, and 9.
+    pub fn standard() -> InstructionSet {
+        let mut set = InstructionSet::empty();
+        for &opcode in Opcode::ALL.iter() {
+            set.insert(opcode);
+        }
+        set
+    }
+
+    // Adds `opcode`, replacing any opcode with the same code.
+    pub fn insert(&mut self, opcode: Opcode) {
+        let code = opcode.code();
+        assert!((1..100).contains(&code), "opcode {} isn't two digits", code);
+        assert!(opcode.params() <= 3, "{:?} has too many parameters", opcode);
+        if let Some(w) = opcode.write_param() {
+            assert!(
+                w < opcode.params(),
+                "{:?} writes a missing parameter",
+                opcode
+            );
+        }
+        self.table[code as usize] = Some(opcode);
+    }
+
+    pub fn remove(&mut self, code: i64) {
+        if let Some(entry) = self.table.get_mut(code as usize) {
+            *entry = None;
+        }
+    }
+
+    // Keeps only the opcodes for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(Opcode) -> bool) {
+        for entry in self.table.iter_mut() {
+            if let Some(opcode) = *entry {
+                if !keep(opcode) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, code: i64) -> Option<Opcode> {
+        if (0..100).contains(&code) {
+            self.table[code as usize]
+        } else {
+            None
+        }
+    }
+
+    // Limits parameters to `modes`.
+    pub fn set_modes(&mut self, modes: &[Mode]) {
+        self.modes = [false; 3];
+        for mode in modes {
+            self.modes[mode.code() as usize] = true;
+        }
+    }
+
+    pub fn allows_mode(&self, mode: Mode) -> bool {
+        self.modes[mode.code() as usize]
+    }
+
+    // The opcodes in the set, in order of their codes.
+    pub fn opcodes(&self) -> impl Iterator<Item = Opcode> + '_ {
+        self.table.iter().flatten().copied()
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> InstructionSet {
+        InstructionSet::standard()
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    // Replaces the opcodes the machine understands. Machines start with
+    // InstructionSet::standard().
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+        self.reset_decode_cache();
+    }
+
+    pub fn instruction_set(&self) -> &InstructionSet {
+        &self.instruction_set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorCause, Machine, RunResult};
+
+    static MOD: CustomOpcode = CustomOpcode {
+        code: 10,
+        mnemonic: "MOD",
+        params: 3,
+        write_param: Some(2),
+        execute: |operands| Effect::Store(operands[0] % operands[1]),
+    };
+
+    static JMP: CustomOpcode = CustomOpcode {
+        code: 11,
+        mnemonic: "JMP",
+        params: 1,
+        write_param: None,
+        execute: |operands| Effect::JumpTo(operands[0]),
+    };
+
+    #[test]
+    fn custom_opcodes() {
+        let mut set = InstructionSet::standard();
+        set.insert(Opcode::Custom(&MOD));
+        set.insert(Opcode::Custom(&JMP));
+        // 17 % 5, then skip over a halt.
+        let program = vec![1110, 17, 5, 11, 111, 8, 99, 99, 4, 11, 99, 0];
+        let mut machine = Machine::new(program.clone());
+        machine.set_instruction_set(set);
+        assert_eq!(machine.run().unwrap(), RunResult::Output(2));
+
+        let err = Machine::new(program).run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::UnknownOpcode(10));
+    }
+
+    #[test]
+    fn restricted_set() {
+        let mut set = InstructionSet::standard();
+        set.retain(|op| matches!(op, Opcode::Add | Opcode::Mul | Opcode::Halt));
+        assert_eq!(
+            set.opcodes().collect::<Vec<_>>(),
+            vec![Opcode::Add, Opcode::Mul, Opcode::Halt]
+        );
+        let mut machine = Machine::new(vec![1, 0, 0, 0, 99]);
+        machine.set_instruction_set(set.clone());
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+        assert_eq!(machine.read(0), 2);
+
+        let mut machine = Machine::new(vec![3, 0, 99]);
+        machine.set_instruction_set(set);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::UnknownOpcode(3));
+    }
+}
@@ -0,0 +1,154 @@
+use super::{ErrorCause, IntcodeError, Machine, Memory, NoTracer, RunResult, Tracer};
+
+// Where a running program's input comes from. Any iterator of i64 is an
+// Input; wrap a closure in std::iter::from_fn() to use it as one.
+pub trait Input {
+    // Returns None if there's no more input, which stops the program with
+    // ErrorCause::MissingInput.
+    fn input(&mut self) -> Option<i64>;
+}
+
+// Where a running program's output goes. Vec<i64> and closures that take an
+// i64 are Outputs.
+pub trait Output {
+    fn output(&mut self, val: i64);
+}
+
+impl<I: Iterator<Item = i64>> Input for I {
+    fn input(&mut self) -> Option<i64> {
+        self.next()
+    }
+}
+
+impl<F: FnMut(i64)> Output for F {
+    fn output(&mut self, val: i64) {
+        self(val)
+    }
+}
+
+impl Output for Vec<i64> {
+    fn output(&mut self, val: i64) {
+        self.push(val)
+    }
+}
+
+// Something that's both an Input and an Output, like a robot that reads its
+// sensors and obeys commands. Use Io to combine a separate Input and Output.
+pub trait Device: Input + Output {}
+impl<T: Input + Output> Device for T {}
+
+pub struct Io<I, O> {
+    pub input: I,
+    pub output: O,
+}
+impl<I: Input, O> Input for Io<I, O> {
+    fn input(&mut self) -> Option<i64> {
+        self.input.input()
+    }
+}
+impl<I, O: Output> Output for Io<I, O> {
+    fn output(&mut self, val: i64) {
+        self.output.output(val)
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    // Runs until the machine halts, asking `device` for input whenever the
+    // input queue is empty and giving it every output. Returns Halted, or
+    // BudgetExhausted if the machine's budget ran out first.
+    pub fn run_device(&mut self, device: &mut dyn Device) -> Result<RunResult, IntcodeError> {
+        self.run_device_traced(device, &mut NoTracer)
+    }
+
+    pub fn run_device_traced<T: Tracer + ?Sized>(
+        &mut self,
+        device: &mut dyn Device,
+        tracer: &mut T,
+    ) -> Result<RunResult, IntcodeError> {
+        loop {
+            match self.run_traced(tracer)? {
+                RunResult::NeedsInput => match device.input() {
+                    Some(val) => self.push_input(val),
+                    None => return Err(self.error(ErrorCause::MissingInput)),
+                },
+                RunResult::Output(val) => device.output(val),
+                result => return Ok(result),
+            }
+        }
+    }
+}
+
+// Runs `program` to completion connected to `device`, and returns its final
+// memory.
+pub fn run_program(program: Vec<i64>, device: &mut dyn Device) -> Result<Vec<i64>, IntcodeError> {
+    let mut machine = Machine::new(program);
+    machine.run_device(device)?;
+    Ok(machine.into_memory())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    // Outputs 1 if its input is 8, and 0 otherwise.
+    const EQUALS_8: [i64; 11] = [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+
+    #[test]
+    fn iterator_in_vec_out() {
+        let mut io = Io {
+            input: vec![8].into_iter(),
+            output: vec![],
+        };
+        let memory = run_program(EQUALS_8.to_vec(), &mut io).unwrap();
+        assert_eq!(io.output, vec![1]);
+        assert_eq!(memory[9], 1);
+    }
+
+    #[test]
+    fn closures() {
+        let mut calls = 0;
+        let mut last = None;
+        let mut io = Io {
+            input: iter::from_fn(|| {
+                calls += 1;
+                Some(7)
+            }),
+            output: |val| last = Some(val),
+        };
+        run_program(EQUALS_8.to_vec(), &mut io).unwrap();
+        assert_eq!((calls, last), (1, Some(0)));
+    }
+
+    #[test]
+    fn device() {
+        // Answers each input request with double the last output.
+        struct Doubler(i64, Vec<i64>);
+        impl Input for Doubler {
+            fn input(&mut self) -> Option<i64> {
+                Some(self.0)
+            }
+        }
+        impl Output for Doubler {
+            fn output(&mut self, val: i64) {
+                self.0 = val * 2;
+                self.1.push(val);
+            }
+        }
+        // Reads and echoes three values.
+        let program = vec![3, 0, 4, 0, 3, 0, 4, 0, 3, 0, 4, 0, 99];
+        let mut doubler = Doubler(1, vec![]);
+        run_program(program, &mut doubler).unwrap();
+        assert_eq!(doubler.1, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn missing_input() {
+        let mut io = Io {
+            input: iter::empty(),
+            output: vec![],
+        };
+        let err = run_program(EQUALS_8.to_vec(), &mut io).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::MissingInput);
+    }
+}
@@ -1,5 +1,55 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use history::History;
+use limits::{Limits, LoopDetector};
+
+mod analysis;
+mod ascii;
+mod asm;
+#[cfg(feature = "async")]
+mod async_io;
+mod compile;
+mod debugger;
+mod dialect;
+mod disasm;
+mod history;
+mod instruction_set;
+mod io;
+mod limits;
+mod loader;
+mod memory;
+mod network;
+mod profile;
+mod replay;
+mod scheduler;
+mod snapshot;
+mod symbolic;
+mod terminal;
+mod trace;
+pub use analysis::*;
+pub use ascii::*;
+pub use asm::*;
+#[cfg(feature = "async")]
+pub use async_io::*;
+pub use compile::*;
+pub use debugger::*;
+pub use dialect::*;
+pub use disasm::*;
+pub use instruction_set::*;
+pub use io::*;
+pub use loader::*;
+pub use memory::*;
+pub use network::*;
+pub use profile::*;
+pub use replay::*;
+pub use scheduler::*;
+pub use snapshot::*;
+pub use symbolic::*;
+pub use terminal::*;
+pub use trace::*;
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct IntcodeResult {
@@ -7,49 +57,486 @@ pub struct IntcodeResult {
     pub output: Vec<i64>,
 }
 
-pub fn run_intcode(mut program: Vec<i64>, state: &mut dyn State) -> IntcodeResult {
-    let mut position = 0;
-    loop {
-        let opcode = Opcode::new(program[position] % 100);
-        let mut allmodes = program[position] / 100;
-        let mut modes: Vec<i64> = vec![];
-        while allmodes != 0 {
-            modes.push(allmodes % 10);
-            allmodes /= 10;
+pub fn run_intcode(
+    program: Vec<i64>,
+    state: &mut dyn State,
+) -> Result<IntcodeResult, IntcodeError> {
+    run_intcode_traced(program, state, &mut NoTracer)
+}
+
+// Like run_intcode(), but reports every executed instruction to `tracer`.
+pub fn run_intcode_traced<T: Tracer + ?Sized>(
+    program: Vec<i64>,
+    state: &mut dyn State,
+    tracer: &mut T,
+) -> Result<IntcodeResult, IntcodeError> {
+    let mut machine = Machine::new(program);
+    machine.run_device_traced(&mut StateDevice(state), tracer)?;
+    Ok(IntcodeResult {
+        memory: machine.into_memory(),
+        output: state.copy_output(),
+    })
+}
+
+pub fn run_intcode_input(program: Vec<i64>, input: &[i64]) -> Result<IntcodeResult, IntcodeError> {
+    run_intcode(program, &mut VecState::new(input.into()))
+}
+
+// Why an Intcode program couldn't continue.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ErrorCause {
+    UnknownOpcode(i64),
+    UnknownMode(i64),
+    WriteToImmediate,
+    // A position, relative address, or jump target was negative.
+    NegativeAddress(i64),
+    // A position or relative address was past the machine's maximum address.
+    AddressOutOfRange(usize),
+    // An addition, multiplication, relative address, or relative base
+    // adjustment didn't fit in an i64. `ip` is the instruction that did it.
+    Overflow { ip: usize },
+    // The instruction pointer ran off the end of memory.
+    IpOutOfRange,
+    // The program executed an input instruction and the caller had nothing to
+    // give it.
+    MissingInput,
+    // With loop detection on, the machine got back to a state it had already
+    // been in without doing any I/O in between.
+    InfiniteLoop,
+}
+impl fmt::Display for ErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCause::UnknownOpcode(code) => write!(f, "unknown opcode {}", code),
+            ErrorCause::UnknownMode(code) => write!(f, "unknown parameter mode {}", code),
+            ErrorCause::WriteToImmediate => write!(f, "can't write to an immediate parameter"),
+            ErrorCause::NegativeAddress(addr) => write!(f, "negative address {}", addr),
+            ErrorCause::AddressOutOfRange(addr) => write!(f, "address {} out of range", addr),
+            ErrorCause::Overflow { .. } => write!(f, "arithmetic overflow"),
+            ErrorCause::IpOutOfRange => write!(f, "instruction pointer past end of memory"),
+            ErrorCause::MissingInput => write!(f, "not enough input"),
+            ErrorCause::InfiniteLoop => write!(f, "infinite loop"),
         }
-        modes.resize(opcode.params(), 0);
-        let modes: Vec<Mode> = modes.into_iter().map(Mode::new).collect();
-        let params = Box::from(&program[position + 1..position + 1 + opcode.params()]);
-        match opcode.execute(&params, &modes, &mut program, state) {
-            OpcodeResult::Continue => position += opcode.params() + 1,
-            OpcodeResult::JumpTo(target) => position = target,
-            OpcodeResult::Halt => {
-                return IntcodeResult {
-                    memory: program,
-                    output: state.copy_output(),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct IntcodeError {
+    // The address of the failing instruction.
+    pub ip: usize,
+    // The raw value at `ip`, including its mode digits.
+    pub instruction: i64,
+    pub cause: ErrorCause,
+}
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} (instruction {})",
+            self.cause, self.ip, self.instruction
+        )
+    }
+}
+impl Error for IntcodeError {}
+
+// What a Machine is waiting on when run() returns.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RunResult {
+    // The next instruction is an input and there's no queued input. Call
+    // push_input() and then run() again.
+    NeedsInput,
+    Output(i64),
+    Halted,
+    // The run hit its instruction budget or deadline. Calling run() again
+    // continues where it left off.
+    BudgetExhausted,
+}
+
+// An Intcode computer that can be paused whenever it needs input or produces
+// output, so callers can interleave several machines on one thread.
+//
+// Memory is a plain Vec<i64> unless the machine is built with_memory().
+#[derive(Clone)]
+pub struct Machine<M: Memory = Vec<i64>> {
+    memory: M,
+    ip: usize,
+    relative_base: i64,
+    input: VecDeque<i64>,
+    // Accessing a higher address is an error.
+    max_address: usize,
+    // decoded[addr] caches the instruction starting at addr, so loops don't
+    // re-decode their instructions on every pass. Writes clear the entries
+    // for any instruction that overlaps the written address. Only the
+    // addresses the program was loaded into are cached, so a far write
+    // doesn't grow the cache along with memory.
+    decoded: Vec<Option<Instruction>>,
+    use_decode_cache: bool,
+    limits: Limits,
+    loop_detector: Option<LoopDetector>,
+    history: Option<History>,
+    instruction_set: InstructionSet,
+    // How many instructions have completed since the machine was created.
+    executed: u64,
+}
+impl Machine {
+    pub fn new(program: Vec<i64>) -> Machine {
+        Machine::with_memory(program)
+    }
+}
+impl<M: Memory> Machine<M> {
+    pub fn with_memory(memory: M) -> Machine<M> {
+        let decoded = vec![None; memory.len()];
+        Machine {
+            memory,
+            ip: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            max_address: M::DEFAULT_MAX_ADDRESS,
+            decoded,
+            use_decode_cache: true,
+            limits: Limits::default(),
+            loop_detector: None,
+            history: None,
+            instruction_set: InstructionSet::standard(),
+            executed: 0,
+        }
+    }
+    pub fn push_input(&mut self, val: i64) {
+        self.input.push_back(val);
+        self.reset_loop_detector();
+    }
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+    pub fn into_memory(self) -> M {
+        self.memory
+    }
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+    // How many instructions the machine has completed, over all runs. An input
+    // instruction that's waiting for input hasn't completed yet.
+    pub fn instructions_executed(&self) -> u64 {
+        self.executed
+    }
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+        self.reset_loop_detector();
+    }
+    pub fn set_relative_base(&mut self, relative_base: i64) {
+        self.relative_base = relative_base;
+        self.reset_loop_detector();
+    }
+    // Makes the program fail with AddressOutOfRange if it reads or writes
+    // past `max_address`. The default depends on the memory backend; see
+    // Memory::DEFAULT_MAX_ADDRESS.
+    pub fn set_max_address(&mut self, max_address: usize) {
+        self.max_address = max_address;
+    }
+    // Reads memory the way the program would, so addresses past the end are 0.
+    pub fn read(&self, addr: usize) -> i64 {
+        self.memory.read(addr)
+    }
+    // Writes memory the way the program would, so it fails past max_address.
+    pub fn write(&mut self, addr: usize, val: i64) -> Result<(), ErrorCause> {
+        if addr > self.max_address {
+            return Err(ErrorCause::AddressOutOfRange(addr));
+        }
+        self.set_cell(addr, val)
+    }
+    // Like write(), but without checking max_address.
+    fn set_cell(&mut self, addr: usize, val: i64) -> Result<(), ErrorCause> {
+        let old = self.memory.read(addr);
+        self.memory.write(addr, val)?;
+        if let Some(detector) = &mut self.loop_detector {
+            detector.write(addr, old, val);
+        }
+        self.forget_decoded(addr);
+        Ok(())
+    }
+    // Input that's been pushed but not read yet.
+    pub fn pending_input(&self) -> impl Iterator<Item = &i64> {
+        self.input.iter()
+    }
+    // The decode cache is on by default. Turning it off makes the machine
+    // decode every instruction it executes, which is only useful for
+    // measuring what the cache buys.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.use_decode_cache = enabled;
+        self.reset_decode_cache();
+    }
+
+    // Drops the cached instructions that overlap `addr`.
+    fn forget_decoded(&mut self, addr: usize) {
+        // An instruction is at most 4 words long.
+        for start in addr.saturating_sub(3)..=addr {
+            if let Some(entry) = self.decoded.get_mut(start) {
+                *entry = None;
+            }
+        }
+    }
+
+    fn reset_decode_cache(&mut self) {
+        self.decoded.clear();
+        if self.use_decode_cache {
+            self.decoded.resize(self.memory.len(), None);
+        }
+    }
+
+    // Runs until the machine halts, produces an output, needs input it
+    // doesn't have, or runs out of any budget set by set_instruction_budget()
+    // or set_deadline(). Calling run() again on a halted machine returns
+    // Halted.
+    //
+    // On error, the instruction pointer is left at the failing instruction.
+    pub fn run(&mut self) -> Result<RunResult, IntcodeError> {
+        self.run_traced(&mut NoTracer)
+    }
+
+    // Like run(), but reports each instruction to `tracer` after it executes.
+    // An input instruction that has to wait isn't reported until it completes.
+    pub fn run_traced<T: Tracer + ?Sized>(
+        &mut self,
+        tracer: &mut T,
+    ) -> Result<RunResult, IntcodeError> {
+        let mut executed = 0;
+        loop {
+            if self.limits.exhausted(executed) {
+                return Ok(RunResult::BudgetExhausted);
+            }
+            let state = match &self.loop_detector {
+                Some(detector) => {
+                    let state = detector.state(self.ip, self.relative_base, self.input.len());
+                    if detector.seen(&state) {
+                        return Err(self.error(ErrorCause::InfiniteLoop));
+                    }
+                    Some(state)
+                }
+                None => None,
+            };
+            let result = self.step_traced(tracer)?;
+            // Waiting for input doesn't leave the state, so run() can find
+            // the machine there again.
+            if let (Some(state), Some(detector)) = (state, &mut self.loop_detector) {
+                if result != Some(RunResult::NeedsInput) {
+                    detector.record(state);
                 }
             }
+            if let Some(result) = result {
+                return Ok(result);
+            }
+            executed += 1;
         }
     }
-}
 
-pub fn run_intcode_input(program: Vec<i64>, input: &[i64]) -> IntcodeResult {
-    run_intcode(program, &mut VecState::new(input.into()))
+    // Executes a single instruction. Returns None if the machine can keep
+    // running, or what it stopped for, like run().
+    pub fn step(&mut self) -> Result<Option<RunResult>, IntcodeError> {
+        self.step_traced(&mut NoTracer)
+    }
+
+    pub fn step_traced<T: Tracer + ?Sized>(
+        &mut self,
+        tracer: &mut T,
+    ) -> Result<Option<RunResult>, IntcodeError> {
+        if self.history.is_none() {
+            return self.try_step(tracer).map_err(|cause| self.error(cause));
+        }
+        let (ip, relative_base, executed) = (self.ip, self.relative_base, self.executed);
+        let (pending_input, len) = (self.input.len(), self.memory.len());
+        let result = self.try_step(tracer).map_err(|cause| self.error(cause));
+        self.record_history(ip, relative_base, executed, pending_input, len);
+        result
+    }
+
+    // Executes one instruction and moves the instruction pointer past it,
+    // unless it halted or is waiting for input. Returns None if the machine
+    // can keep running.
+    fn try_step<T: Tracer + ?Sized>(
+        &mut self,
+        tracer: &mut T,
+    ) -> Result<Option<RunResult>, ErrorCause> {
+        let instruction = self.fetch()?;
+        if tracer.enabled() {
+            let ip = self.ip;
+            let relative_base = self.relative_base;
+            let operands = self.resolve(&instruction, self.operands_read(&instruction)?)?;
+            let result = instruction.opcode.execute(&instruction, self)?;
+            if let OpcodeResult::NeedsInput = result {
+                return Ok(Some(RunResult::NeedsInput));
+            }
+            let write = instruction.opcode.write_param().map(|w| {
+                let addr = operands[w] as usize;
+                (addr, self.memory.read(addr))
+            });
+            let params = instruction.opcode.params();
+            tracer.trace(&TraceStep {
+                ip,
+                opcode: instruction.opcode,
+                modes: &instruction.modes[..params],
+                params: &instruction.params[..params],
+                operands: &operands,
+                relative_base,
+                write,
+            });
+            return Ok(self.advance(&instruction, result));
+        }
+        let result = instruction.opcode.execute(&instruction, self)?;
+        Ok(self.advance(&instruction, result))
+    }
+
+    fn fetch(&mut self) -> Result<Instruction, ErrorCause> {
+        if self.ip >= self.memory.len() {
+            return Err(ErrorCause::IpOutOfRange);
+        }
+        match self.decoded.get(self.ip) {
+            Some(Some(instruction)) => Ok(*instruction),
+            Some(None) => {
+                let instruction =
+                    Instruction::decode(&self.instruction_set, &self.memory, self.ip)?;
+                self.decoded[self.ip] = Some(instruction);
+                Ok(instruction)
+            }
+            None => Instruction::decode(&self.instruction_set, &self.memory, self.ip),
+        }
+    }
+
+    // How many of the instruction's parameters execute() will resolve, so
+    // tracing doesn't fail on one the instruction never uses. A jump that
+    // isn't taken doesn't read its target, and an input instruction with
+    // nothing to read doesn't get as far as its address.
+    fn operands_read(&self, instruction: &Instruction) -> Result<usize, ErrorCause> {
+        Ok(match instruction.opcode {
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let taken =
+                    (self.load(instruction, 0)? != 0) == (instruction.opcode == Opcode::JumpIfTrue);
+                if taken {
+                    2
+                } else {
+                    1
+                }
+            }
+            Opcode::In if self.input.is_empty() => 0,
+            opcode => opcode.params(),
+        })
+    }
+
+    // Resolves the first `count` parameters to the value each reads, or to
+    // the address it writes.
+    fn resolve(&self, instruction: &Instruction, count: usize) -> Result<Vec<i64>, ErrorCause> {
+        let write_param = instruction.opcode.write_param();
+        (0..count)
+            .map(|i| {
+                if write_param == Some(i) {
+                    self.address(instruction, i).map(|addr| addr as i64)
+                } else {
+                    self.load(instruction, i)
+                }
+            })
+            .collect()
+    }
+
+    // The address a position or relative mode parameter refers to.
+    fn address(&self, instruction: &Instruction, param: usize) -> Result<usize, ErrorCause> {
+        let addr = instruction.modes[param].address(
+            instruction.params[param],
+            self.relative_base,
+            self.ip,
+        )?;
+        if addr > self.max_address {
+            return Err(ErrorCause::AddressOutOfRange(addr));
+        }
+        Ok(addr)
+    }
+
+    fn load(&self, instruction: &Instruction, param: usize) -> Result<i64, ErrorCause> {
+        match instruction.modes[param] {
+            Mode::Immediate => Ok(instruction.params[param]),
+            _ => Ok(self.memory.read(self.address(instruction, param)?)),
+        }
+    }
+
+    fn store(
+        &mut self,
+        instruction: &Instruction,
+        param: usize,
+        val: i64,
+    ) -> Result<(), ErrorCause> {
+        let addr = self.address(instruction, param)?;
+        if let Some(history) = &mut self.history {
+            history.store(addr, self.memory.read(addr));
+        }
+        self.set_cell(addr, val)?;
+        Ok(())
+    }
+
+    fn advance(&mut self, instruction: &Instruction, result: OpcodeResult) -> Option<RunResult> {
+        let stop = match result {
+            OpcodeResult::Continue => {
+                self.ip += instruction.len();
+                None
+            }
+            OpcodeResult::JumpTo(target) => {
+                self.ip = target;
+                None
+            }
+            OpcodeResult::Output(val) => {
+                self.ip += instruction.len();
+                Some(RunResult::Output(val))
+            }
+            OpcodeResult::NeedsInput => return Some(RunResult::NeedsInput),
+            OpcodeResult::Halt => Some(RunResult::Halted),
+        };
+        self.executed += 1;
+        stop
+    }
+
+    fn error(&self, cause: ErrorCause) -> IntcodeError {
+        IntcodeError {
+            ip: self.ip,
+            instruction: self.memory.read(self.ip),
+            cause,
+        }
+    }
 }
 
+// The original interface for run_intcode(). New code should implement Input
+// and Output and use run_program() or Machine::run_device() instead.
+//
+// The Machine keeps its own relative base, so the relative base methods are
+// never called, and are only here so older implementations still compile.
 pub trait State {
     fn input(&mut self) -> Option<i64>;
     fn output(&mut self, val: i64);
-    fn copy_output(&self) -> Vec<i64>;
-    fn adjust_relative_base(&mut self, adjust: i64);
-    fn relative_base(&self) -> i64;
+    // Becomes IntcodeResult::output.
+    fn copy_output(&self) -> Vec<i64> {
+        vec![]
+    }
+    fn adjust_relative_base(&mut self, _adjust: i64) {}
+    fn relative_base(&self) -> i64 {
+        0
+    }
+}
+
+struct StateDevice<'a>(&'a mut dyn State);
+impl Input for StateDevice<'_> {
+    fn input(&mut self) -> Option<i64> {
+        self.0.input()
+    }
+}
+impl Output for StateDevice<'_> {
+    fn output(&mut self, val: i64) {
+        self.0.output(val)
+    }
 }
 
 pub struct VecState {
     input: Vec<i64>,
     input_pos: usize,
     output: Vec<i64>,
-    relative_base: i64,
 }
 impl VecState {
     pub fn new(input: Vec<i64>) -> VecState {
@@ -57,7 +544,6 @@ impl VecState {
             input,
             input_pos: 0,
             output: vec![],
-            relative_base: 0,
         }
     }
 }
@@ -76,103 +562,109 @@ impl State for VecState {
     fn copy_output(&self) -> Vec<i64> {
         self.output.clone()
     }
-    fn adjust_relative_base(&mut self, adjust: i64) {
-        self.relative_base += adjust;
-    }
-    fn relative_base(&self) -> i64 {
-        self.relative_base
-    }
-}
-
-fn extend_read(v: &Vec<i64>, index: usize) -> i64 {
-    if index >= v.len() {
-        0
-    } else {
-        v[index]
-    }
 }
 
-fn extend_write(v: &mut Vec<i64>, index: usize, val: i64) {
-    if index >= v.len() {
-        v.resize(index + 1, 0);
-    }
-    v[index] = val;
-}
-
-#[derive(Clone, Copy)]
-enum Mode {
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Mode {
     Position,
     Immediate,
     Relative,
 }
 impl Mode {
-    fn new(code: i64) -> Mode {
+    fn new(code: i64) -> Result<Mode, ErrorCause> {
         match code {
-            0 => Mode::Position,
-            1 => Mode::Immediate,
-            2 => Mode::Relative,
-            _ => panic!("Unknown mode: {}", code),
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            _ => Err(ErrorCause::UnknownMode(code)),
         }
     }
-    fn load(&self, arg: i64, relative_base: i64, memory: &Vec<i64>) -> i64 {
+    fn code(&self) -> i64 {
         match self {
-            Mode::Position => extend_read(memory, usize::try_from(arg).unwrap()),
-            Mode::Immediate => arg,
-            Mode::Relative => extend_read(memory, usize::try_from(arg + relative_base).unwrap()),
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
         }
     }
-    fn write(&self, arg: i64, relative_base: i64, memory: &mut Vec<i64>, value: i64) {
+    // The address a parameter of the instruction at `ip` refers to.
+    // Immediate parameters don't have one, so they can't be written.
+    fn address(&self, arg: i64, relative_base: i64, ip: usize) -> Result<usize, ErrorCause> {
         match self {
-            Mode::Position => extend_write(memory, usize::try_from(arg).unwrap(), value),
-            Mode::Immediate => panic!("Can't write to immediate"),
-            Mode::Relative => {
-                extend_write(memory, usize::try_from(arg + relative_base).unwrap(), value)
-            }
+            Mode::Position => address(arg),
+            Mode::Immediate => Err(ErrorCause::WriteToImmediate),
+            Mode::Relative => address(
+                arg.checked_add(relative_base)
+                    .ok_or(ErrorCause::Overflow { ip })?,
+            ),
         }
     }
 }
 
-trait Modes {
-    fn load(
-        &self,
-        params: &[i64],
-        which_param: usize,
-        relative_base: i64,
-        memory: &Vec<i64>,
-    ) -> i64;
-    fn write(
-        &self,
-        params: &[i64],
-        which_param: usize,
-        relative_base: i64,
-        memory: &mut Vec<i64>,
-        value: i64,
-    );
+// Splits a raw instruction into its standard opcode and the modes of its
+// parameters. Mode digits beyond the opcode's parameter count are ignored.
+fn decode(instruction: i64) -> Result<(Opcode, [Mode; 3]), ErrorCause> {
+    decode_modes(Opcode::new(instruction % 100)?, instruction)
 }
-impl Modes for [Mode] {
-    fn load(
-        &self,
-        params: &[i64],
-        which_param: usize,
-        relative_base: i64,
-        memory: &Vec<i64>,
-    ) -> i64 {
-        self[which_param].load(params[which_param], relative_base, memory)
-    }
-    fn write(
-        &self,
-        params: &[i64],
-        which_param: usize,
-        relative_base: i64,
-        memory: &mut Vec<i64>,
-        value: i64,
-    ) {
-        self[which_param].write(params[which_param], relative_base, memory, value)
+
+// Like decode(), but for the opcodes in `set`.
+fn decode_with(set: &InstructionSet, instruction: i64) -> Result<(Opcode, [Mode; 3]), ErrorCause> {
+    let code = instruction % 100;
+    let opcode = set.get(code).ok_or(ErrorCause::UnknownOpcode(code))?;
+    let (opcode, modes) = decode_modes(opcode, instruction)?;
+    if let Some(&mode) = modes[..opcode.params()]
+        .iter()
+        .find(|&&mode| !set.allows_mode(mode))
+    {
+        return Err(ErrorCause::UnknownMode(mode.code()));
     }
+    Ok((opcode, modes))
+}
+
+fn decode_modes(opcode: Opcode, instruction: i64) -> Result<(Opcode, [Mode; 3]), ErrorCause> {
+    let mut allmodes = instruction / 100;
+    let mut modes = [Mode::Position; 3];
+    for mode in modes.iter_mut().take(opcode.params()) {
+        *mode = Mode::new(allmodes % 10)?;
+        allmodes /= 10;
+    }
+    Ok((opcode, modes))
+}
+
+fn address(addr: i64) -> Result<usize, ErrorCause> {
+    usize::try_from(addr).map_err(|_| ErrorCause::NegativeAddress(addr))
 }
 
+// A decoded instruction. Parameters past the opcode's count are unused.
 #[derive(Clone, Copy)]
-enum Opcode {
+struct Instruction {
+    opcode: Opcode,
+    modes: [Mode; 3],
+    params: [i64; 3],
+}
+impl Instruction {
+    fn decode<M: Memory>(
+        set: &InstructionSet,
+        memory: &M,
+        ip: usize,
+    ) -> Result<Instruction, ErrorCause> {
+        let (opcode, modes) = decode_with(set, memory.read(ip))?;
+        let mut params = [0; 3];
+        for (i, param) in params.iter_mut().enumerate().take(opcode.params()) {
+            *param = memory.read(ip + 1 + i);
+        }
+        Ok(Instruction {
+            opcode,
+            modes,
+            params,
+        })
+    }
+    fn len(&self) -> usize {
+        self.opcode.params() + 1
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Opcode {
     Add,
     Mul,
     In,
@@ -183,10 +675,24 @@ enum Opcode {
     Equals,
     AdjustRelativeBase,
     Halt,
+    // An opcode added to a Machine's InstructionSet.
+    Custom(&'static CustomOpcode),
 }
 impl Opcode {
-    fn new(code: i64) -> Opcode {
-        match code {
+    const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Mul,
+        Opcode::In,
+        Opcode::Out,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::AdjustRelativeBase,
+        Opcode::Halt,
+    ];
+    fn new(code: i64) -> Result<Opcode, ErrorCause> {
+        Ok(match code {
             1 => Opcode::Add,
             2 => Opcode::Mul,
             3 => Opcode::In,
@@ -197,10 +703,25 @@ impl Opcode {
             8 => Opcode::Equals,
             9 => Opcode::AdjustRelativeBase,
             99 => Opcode::Halt,
-            _ => panic!("Unexpected opcode: {}", code),
+            _ => return Err(ErrorCause::UnknownOpcode(code)),
+        })
+    }
+    pub fn code(&self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::In => 3,
+            Opcode::Out => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Halt => 99,
+            Opcode::Custom(def) => def.code,
         }
     }
-    fn params(&self) -> usize {
+    pub fn params(&self) -> usize {
         match self {
             Opcode::Add => 3,
             Opcode::Mul => 3,
@@ -212,96 +733,104 @@ impl Opcode {
             Opcode::Equals => 3,
             Opcode::AdjustRelativeBase => 1,
             Opcode::Halt => 0,
+            Opcode::Custom(def) => def.params,
+        }
+    }
+    // The parameter this opcode stores its result through, if any.
+    fn write_param(&self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => Some(2),
+            Opcode::In => Some(0),
+            Opcode::Custom(def) => def.write_param,
+            _ => None,
+        }
+    }
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::In => "IN",
+            Opcode::Out => "OUT",
+            Opcode::JumpIfTrue => "JT",
+            Opcode::JumpIfFalse => "JF",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::AdjustRelativeBase => "ARB",
+            Opcode::Halt => "HLT",
+            Opcode::Custom(def) => def.mnemonic,
         }
     }
-    fn execute(
+    fn execute<M: Memory>(
         &self,
-        params: &[i64],
-        modes: &[Mode],
-        program: &mut Vec<i64>,
-        state: &mut dyn State,
-    ) -> OpcodeResult {
-        assert_eq!(self.params(), params.len());
-        assert_eq!(params.len(), modes.len());
+        instruction: &Instruction,
+        machine: &mut Machine<M>,
+    ) -> Result<OpcodeResult, ErrorCause> {
+        let load = |machine: &Machine<M>, param| machine.load(instruction, param);
+        let overflow = ErrorCause::Overflow { ip: machine.ip };
         match self {
             Opcode::Add => {
-                modes.write(
-                    params,
-                    2,
-                    state.relative_base(),
-                    program,
-                    modes.load(params, 0, state.relative_base(), program)
-                        + modes.load(params, 1, state.relative_base(), program),
-                );
+                let sum = load(machine, 0)?
+                    .checked_add(load(machine, 1)?)
+                    .ok_or(overflow)?;
+                machine.store(instruction, 2, sum)?;
             }
             Opcode::Mul => {
-                modes.write(
-                    params,
-                    2,
-                    state.relative_base(),
-                    program,
-                    modes.load(params, 0, state.relative_base(), program)
-                        * modes.load(params, 1, state.relative_base(), program),
-                );
-            }
-            Opcode::In => {
-                modes.write(
-                    params,
-                    0,
-                    state.relative_base(),
-                    program,
-                    state.input().expect("Not enough input"),
-                );
+                let product = load(machine, 0)?
+                    .checked_mul(load(machine, 1)?)
+                    .ok_or(overflow)?;
+                machine.store(instruction, 2, product)?;
             }
+            Opcode::In => match machine.input.pop_front() {
+                Some(val) => machine.store(instruction, 0, val)?,
+                None => return Ok(OpcodeResult::NeedsInput),
+            },
             Opcode::Out => {
-                state.output(modes.load(params, 0, state.relative_base(), program));
+                return Ok(OpcodeResult::Output(load(machine, 0)?));
             }
             Opcode::JumpIfTrue => {
-                if modes.load(params, 0, state.relative_base(), program) != 0 {
-                    return OpcodeResult::JumpTo(
-                        modes
-                            .load(params, 1, state.relative_base(), program)
-                            .try_into()
-                            .unwrap(),
-                    );
+                if load(machine, 0)? != 0 {
+                    return Ok(OpcodeResult::JumpTo(address(load(machine, 1)?)?));
                 }
             }
             Opcode::JumpIfFalse => {
-                if modes.load(params, 0, state.relative_base(), program) == 0 {
-                    return OpcodeResult::JumpTo(
-                        modes
-                            .load(params, 1, state.relative_base(), program)
-                            .try_into()
-                            .unwrap(),
-                    );
+                if load(machine, 0)? == 0 {
+                    return Ok(OpcodeResult::JumpTo(address(load(machine, 1)?)?));
                 }
             }
             Opcode::LessThan => {
-                if modes.load(params, 0, state.relative_base(), program)
-                    < modes.load(params, 1, state.relative_base(), program)
-                {
-                    modes.write(params, 2, state.relative_base(), program, 1);
-                } else {
-                    modes.write(params, 2, state.relative_base(), program, 0);
-                }
+                let less = load(machine, 0)? < load(machine, 1)?;
+                machine.store(instruction, 2, i64::from(less))?;
             }
             Opcode::Equals => {
-                if modes.load(params, 0, state.relative_base(), program)
-                    == modes.load(params, 1, state.relative_base(), program)
-                {
-                    modes.write(params, 2, state.relative_base(), program, 1);
-                } else {
-                    modes.write(params, 2, state.relative_base(), program, 0);
-                }
+                let equal = load(machine, 0)? == load(machine, 1)?;
+                machine.store(instruction, 2, i64::from(equal))?;
             }
             Opcode::AdjustRelativeBase => {
-                state.adjust_relative_base(modes.load(params, 0, state.relative_base(), program));
+                machine.relative_base = machine
+                    .relative_base
+                    .checked_add(load(machine, 0)?)
+                    .ok_or(overflow)?;
             }
             Opcode::Halt => {
-                return OpcodeResult::Halt;
+                return Ok(OpcodeResult::Halt);
+            }
+            Opcode::Custom(def) => {
+                let operands = machine.resolve(instruction, def.params)?;
+                match (def.execute)(&operands) {
+                    Effect::Continue => {}
+                    Effect::Store(val) => {
+                        let w = def
+                            .write_param
+                            .expect("Effect::Store without a write_param");
+                        machine.store(instruction, w, val)?;
+                    }
+                    Effect::JumpTo(target) => return Ok(OpcodeResult::JumpTo(address(target)?)),
+                    Effect::Output(val) => return Ok(OpcodeResult::Output(val)),
+                    Effect::Halt => return Ok(OpcodeResult::Halt),
+                }
             }
         }
-        OpcodeResult::Continue
+        Ok(OpcodeResult::Continue)
     }
 }
 
@@ -309,6 +838,8 @@ enum OpcodeResult {
     Continue,
     Halt,
     JumpTo(usize),
+    Output(i64),
+    NeedsInput,
 }
 
 #[cfg(test)]
@@ -317,19 +848,23 @@ mod tests {
     #[test]
     fn examples_day2() {
         assert_eq!(
-            run_intcode_input(vec![1, 0, 0, 0, 99], &[]).memory,
+            run_intcode_input(vec![1, 0, 0, 0, 99], &[]).unwrap().memory,
             vec![2, 0, 0, 0, 99]
         );
         assert_eq!(
-            run_intcode_input(vec![2, 3, 0, 3, 99], &[]).memory,
+            run_intcode_input(vec![2, 3, 0, 3, 99], &[]).unwrap().memory,
             vec![2, 3, 0, 6, 99]
         );
         assert_eq!(
-            run_intcode_input(vec![2, 4, 4, 5, 99, 0], &[]).memory,
+            run_intcode_input(vec![2, 4, 4, 5, 99, 0], &[])
+                .unwrap()
+                .memory,
             vec![2, 4, 4, 5, 99, 9801]
         );
         assert_eq!(
-            run_intcode_input(vec![1, 1, 1, 4, 99, 5, 6, 0, 99], &[]).memory,
+            run_intcode_input(vec![1, 1, 1, 4, 99, 5, 6, 0, 99], &[])
+                .unwrap()
+                .memory,
             vec![30, 1, 1, 4, 2, 5, 6, 0, 99]
         );
     }
@@ -337,49 +872,103 @@ mod tests {
     #[test]
     fn examples_day5_position_eq8() {
         let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
-        assert_eq!(run_intcode_input(program.clone(), &[7]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[8]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[9]).output, vec![0]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[7]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[8]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[9]).unwrap().output,
+            vec![0]
+        );
     }
 
     #[test]
     fn examples_day5_position_lt8() {
         let program = vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8];
-        assert_eq!(run_intcode_input(program.clone(), &[7]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[8]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[9]).output, vec![0]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[7]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[8]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[9]).unwrap().output,
+            vec![0]
+        );
     }
 
     #[test]
     fn examples_day5_imm_eq8() {
         let program = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
-        assert_eq!(run_intcode_input(program.clone(), &[7]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[8]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[9]).output, vec![0]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[7]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[8]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[9]).unwrap().output,
+            vec![0]
+        );
     }
 
     #[test]
     fn examples_day5_imm_lt8() {
         let program = vec![3, 3, 1107, -1, 8, 3, 4, 3, 99];
-        assert_eq!(run_intcode_input(program.clone(), &[7]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[8]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[9]).output, vec![0]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[7]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[8]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[9]).unwrap().output,
+            vec![0]
+        );
     }
 
     #[test]
     fn examples_day5_jump_pos_nonzero() {
         let program = vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
-        assert_eq!(run_intcode_input(program.clone(), &[-1]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[0]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[1]).output, vec![1]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[-1]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[0]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[1]).unwrap().output,
+            vec![1]
+        );
     }
 
     #[test]
     fn examples_day5_jump_imm_nonzero() {
         let program = vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
-        assert_eq!(run_intcode_input(program.clone(), &[-1]).output, vec![1]);
-        assert_eq!(run_intcode_input(program.clone(), &[0]).output, vec![0]);
-        assert_eq!(run_intcode_input(program.clone(), &[1]).output, vec![1]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[-1]).unwrap().output,
+            vec![1]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[0]).unwrap().output,
+            vec![0]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[1]).unwrap().output,
+            vec![1]
+        );
     }
 
     #[test]
@@ -389,9 +978,18 @@ mod tests {
             0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
             20, 1105, 1, 46, 98, 99,
         ];
-        assert_eq!(run_intcode_input(program.clone(), &[7]).output, vec![999]);
-        assert_eq!(run_intcode_input(program.clone(), &[8]).output, vec![1000]);
-        assert_eq!(run_intcode_input(program.clone(), &[9]).output, vec![1001]);
+        assert_eq!(
+            run_intcode_input(program.clone(), &[7]).unwrap().output,
+            vec![999]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[8]).unwrap().output,
+            vec![1000]
+        );
+        assert_eq!(
+            run_intcode_input(program.clone(), &[9]).unwrap().output,
+            vec![1001]
+        );
     }
 
     #[test]
@@ -401,18 +999,181 @@ mod tests {
                 vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99],
                 &[]
             )
+            .unwrap()
             .output,
             vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]
         );
 
         assert_eq!(
-            run_intcode_input(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[]).output,
+            run_intcode_input(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[])
+                .unwrap()
+                .output,
             vec![1219070632396864]
         );
 
         assert_eq!(
-            run_intcode_input(vec![104, 1125899906842624, 99], &[]).output,
+            run_intcode_input(vec![104, 1125899906842624, 99], &[])
+                .unwrap()
+                .output,
             vec![1125899906842624]
         );
     }
+
+    #[test]
+    fn machine_pauses_for_input_and_output() {
+        let mut machine = Machine::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert_eq!(machine.run().unwrap(), RunResult::NeedsInput);
+        assert_eq!(machine.run().unwrap(), RunResult::NeedsInput);
+        machine.push_input(8);
+        assert_eq!(machine.run().unwrap(), RunResult::Output(1));
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+    }
+
+    #[test]
+    fn machines_interleave_on_one_thread() {
+        // Day 7's feedback loop example, without threads.
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut amps: Vec<Machine> = [9, 8, 7, 6, 5]
+            .iter()
+            .map(|&phase| {
+                let mut amp = Machine::new(program.clone());
+                amp.push_input(phase);
+                amp
+            })
+            .collect();
+        let mut signal = 0;
+        let mut halted = false;
+        while !halted {
+            for amp in amps.iter_mut() {
+                amp.push_input(signal);
+                match amp.run().unwrap() {
+                    RunResult::Output(val) => signal = val,
+                    RunResult::Halted => halted = true,
+                    RunResult::NeedsInput | RunResult::BudgetExhausted => {
+                        panic!("Amplifier starved")
+                    }
+                }
+            }
+        }
+        assert_eq!(signal, 139629729);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            run_intcode_input(vec![1, 0, 0, 0, 42], &[]),
+            Err(IntcodeError {
+                ip: 4,
+                instruction: 42,
+                cause: ErrorCause::UnknownOpcode(42)
+            })
+        );
+        assert_eq!(
+            run_intcode_input(vec![301, 0, 0, 0, 99], &[])
+                .unwrap_err()
+                .cause,
+            ErrorCause::UnknownMode(3)
+        );
+        assert_eq!(
+            run_intcode_input(vec![11101, 0, 0, 0, 99], &[])
+                .unwrap_err()
+                .cause,
+            ErrorCause::WriteToImmediate
+        );
+        assert_eq!(
+            run_intcode_input(vec![1, -1, 0, 0, 99], &[])
+                .unwrap_err()
+                .cause,
+            ErrorCause::NegativeAddress(-1)
+        );
+        assert_eq!(
+            run_intcode_input(vec![1105, 1, -7], &[]).unwrap_err().cause,
+            ErrorCause::NegativeAddress(-7)
+        );
+        assert_eq!(
+            run_intcode_input(vec![1101, 0, 0, 0], &[]),
+            Err(IntcodeError {
+                ip: 4,
+                instruction: 0,
+                cause: ErrorCause::IpOutOfRange
+            })
+        );
+        assert_eq!(
+            run_intcode_input(vec![104, 5, 3, 0, 99], &[]),
+            Err(IntcodeError {
+                ip: 2,
+                instruction: 3,
+                cause: ErrorCause::MissingInput
+            })
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let overflow = |program: Vec<i64>, ip: usize| {
+            assert_eq!(
+                run_intcode_input(program.clone(), &[]).unwrap_err().cause,
+                ErrorCause::Overflow { ip },
+                "{:?}",
+                program
+            );
+        };
+        overflow(vec![104, 0, 1101, i64::MAX, 1, 0, 99], 2);
+        overflow(vec![1102, i64::MIN, -1, 0, 99], 0);
+        overflow(vec![109, i64::MAX, 109, 1, 99], 2);
+        overflow(vec![109, i64::MAX, 21101, 0, 0, 1, 99], 2);
+        // Reading through a relative address overflows too.
+        overflow(vec![109, i64::MIN, 204, -1, 99], 2);
+    }
+
+    #[test]
+    fn tracing_doesnt_change_behavior() {
+        let programs = vec![
+            // Jumps that aren't taken, to addresses that don't exist.
+            vec![5, 7, -1, 104, 1, 99, 0, 0],
+            vec![6, 8, 9, 104, 1, 99, 0, 0, 1, -1],
+            vec![2105, 0, -10, 99],
+            // An input to a bad address, with no input to give.
+            vec![3, -1, 99],
+        ];
+        for program in programs {
+            let traced = run_intcode_traced(
+                program.clone(),
+                &mut VecState::new(vec![]),
+                &mut |_: &TraceStep| {},
+            );
+            assert_eq!(
+                traced,
+                run_intcode_input(program.clone(), &[]),
+                "{:?}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modifying_code() {
+        let program = assemble(
+            "start: out #1
+                    add [start+1], #1 -> [start+1]
+                    lt [start+1], #4 -> [flag]
+                    jt [flag], #start
+                    hlt
+             flag:  data 0",
+        )
+        .unwrap();
+        for &cache in &[true, false] {
+            let mut machine = Machine::new(program.clone());
+            machine.set_decode_cache(cache);
+            let mut output = vec![];
+            while let RunResult::Output(val) = machine.run().unwrap() {
+                output.push(val);
+            }
+            assert_eq!(output, vec![1, 2, 3]);
+        }
+    }
 }
@@ -0,0 +1,256 @@
+use super::{Machine, Memory};
+use std::collections::HashSet;
+use std::time::Instant;
+
+// Checking the clock on every instruction would dominate the run time, so the
+// deadline is only checked this often.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// Limits on a single call to Machine::run(). When one runs out, run() returns
+// RunResult::BudgetExhausted and the machine can be resumed by calling run()
+// again, which starts a fresh instruction budget.
+#[derive(Clone, Default)]
+pub(crate) struct Limits {
+    instructions: Option<u64>,
+    deadline: Option<Instant>,
+}
+impl Limits {
+    pub(crate) fn exhausted(&self, executed: u64) -> bool {
+        if let Some(budget) = self.instructions {
+            if executed >= budget {
+                return true;
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if executed.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// An (ip, relative base, memory hash, pending input) state.
+pub(crate) type LoopState = (usize, i64, u64, usize);
+
+// Remembers every state a machine has completed an instruction from since it
+// was last given input or moved by its caller, across run() calls. Intcode is
+// deterministic, so seeing one twice means the program will never stop.
+//
+// Memory is summarized by a hash that's updated on each write rather than
+// recomputed, so a state costs about the same to record whatever the size of
+// memory. Each cell contributes independently, and zero cells contribute
+// nothing, so cells that were never written don't need to be visited.
+//
+// The hash is 64 bits, so detection is probabilistic: two different memories
+// that hash the same would be reported as a loop. Over n states that happens
+// with probability about n^2 / 2^65, which is negligible for any run that
+// fits in memory.
+#[derive(Clone, Default)]
+pub(crate) struct LoopDetector {
+    memory_hash: u64,
+    seen: HashSet<LoopState>,
+}
+impl LoopDetector {
+    fn new<M: Memory>(memory: &M) -> LoopDetector {
+        let mut detector = LoopDetector::default();
+        detector.rehash(memory);
+        detector
+    }
+    pub(crate) fn rehash<M: Memory>(&mut self, memory: &M) {
+        self.memory_hash = memory.nonzero().fold(0, |hash, (addr, val)| {
+            hash.wrapping_add(cell_hash(addr, val))
+        });
+    }
+    pub(crate) fn write(&mut self, addr: usize, old: i64, new: i64) {
+        self.memory_hash = self
+            .memory_hash
+            .wrapping_sub(cell_hash(addr, old))
+            .wrapping_add(cell_hash(addr, new));
+    }
+    pub(crate) fn reset(&mut self) {
+        self.seen.clear();
+    }
+    pub(crate) fn state(&self, ip: usize, relative_base: i64, pending_input: usize) -> LoopState {
+        (ip, relative_base, self.memory_hash, pending_input)
+    }
+    pub(crate) fn seen(&self, state: &LoopState) -> bool {
+        self.seen.contains(state)
+    }
+    pub(crate) fn record(&mut self, state: LoopState) {
+        self.seen.insert(state);
+    }
+}
+
+fn cell_hash(addr: usize, val: i64) -> u64 {
+    if val == 0 {
+        return 0;
+    }
+    mix(mix(addr as u64) ^ val as u64)
+}
+
+// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl<M: Memory> Machine<M> {
+    // Makes each run() stop after executing `instructions` instructions.
+    pub fn set_instruction_budget(&mut self, instructions: Option<u64>) {
+        self.limits.instructions = instructions;
+    }
+
+    pub fn instruction_budget(&self) -> Option<u64> {
+        self.limits.instructions
+    }
+
+    // Makes run() stop once `deadline` has passed. The deadline is absolute,
+    // so it also applies to later runs until it's changed.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.limits.deadline = deadline;
+    }
+
+    // Makes run() fail with ErrorCause::InfiniteLoop if the machine returns
+    // to a state it was already in, even in an earlier run() call. Pushing
+    // input, restoring a snapshot, stepping back, or setting the ip or
+    // relative base starts over. This records every state the machine passes
+    // through, so it's off by default. See LoopDetector for how reliable it
+    // is.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = if enabled {
+            Some(LoopDetector::new(&self.memory))
+        } else {
+            None
+        };
+    }
+
+    // Forgets the states the loop detector has seen, after the caller
+    // changes what the program will do next.
+    pub(crate) fn reset_loop_detector(&mut self) {
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, ErrorCause, Machine, PagedMemory, RunResult};
+    use std::time::{Duration, Instant};
+
+    fn spin() -> Vec<i64> {
+        assemble(
+            "loop: add [count], #1 -> [count]
+                   jt #1, #loop
+            count: data 0",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn budget_pauses_and_resumes() {
+        let program = assemble(
+            "      add #20, #1 -> [x]
+                   mul [x], #2 -> [x]
+                   out [x]
+                   hlt
+                x: data 0",
+        )
+        .unwrap();
+        let mut machine = Machine::new(program);
+        machine.set_instruction_budget(Some(2));
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+        assert_eq!(machine.ip(), 8);
+        assert_eq!(machine.run().unwrap(), RunResult::Output(42));
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+    }
+
+    #[test]
+    fn budget_stops_a_spinning_program() {
+        let mut machine = Machine::new(spin());
+        machine.set_instruction_budget(Some(1000));
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+        assert_eq!(machine.read(7), 500);
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+        assert_eq!(machine.read(7), 1000);
+    }
+
+    #[test]
+    fn deadline() {
+        let mut machine = Machine::new(spin());
+        machine.set_deadline(Some(Instant::now() + Duration::from_millis(10)));
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+        assert!(machine.read(7) > 0);
+    }
+
+    #[test]
+    fn loop_detection() {
+        // The counter changes memory on every pass, so this isn't a loop.
+        let mut machine = Machine::new(spin());
+        machine.set_loop_detection(true);
+        machine.set_instruction_budget(Some(10_000));
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+
+        let program = assemble(
+            "      in -> [x]
+            loop:  jt [x], #loop
+                   out [x]
+                   hlt
+                x: data 0",
+        )
+        .unwrap();
+        let mut machine = Machine::new(program.clone());
+        machine.set_loop_detection(true);
+        machine.push_input(0);
+        assert_eq!(machine.run().unwrap(), RunResult::Output(0));
+
+        let mut machine = Machine::new(program);
+        machine.set_loop_detection(true);
+        machine.push_input(1);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::InfiniteLoop);
+        assert_eq!(err.ip, 2);
+    }
+
+    #[test]
+    fn loop_detection_across_runs() {
+        // A three-instruction loop, run two instructions at a time.
+        let program = assemble(
+            "loop: add #0, #0 -> [x]
+                   add #0, #0 -> [x]
+                   jt #1, #loop
+             x:    data 0",
+        )
+        .unwrap();
+        let mut machine = Machine::new(program);
+        machine.set_loop_detection(true);
+        machine.set_instruction_budget(Some(2));
+        assert_eq!(machine.run().unwrap(), RunResult::BudgetExhausted);
+        assert_eq!(machine.run().unwrap_err().cause, ErrorCause::InfiniteLoop);
+
+        // Waiting for input isn't looping, however often run() is called.
+        let mut machine = Machine::new(vec![3, 3, 99, 0]);
+        machine.set_loop_detection(true);
+        assert_eq!(machine.run().unwrap(), RunResult::NeedsInput);
+        assert_eq!(machine.run().unwrap(), RunResult::NeedsInput);
+        machine.push_input(1);
+        assert_eq!(machine.run().unwrap(), RunResult::Halted);
+    }
+
+    #[test]
+    fn loop_detection_in_sparse_memory() {
+        // Turning detection on hashes memory, which mustn't visit every
+        // address below a far write.
+        let far = 1 << 40;
+        let program = vec![1101, 1, 0, far, 1105, 1, 4];
+        let mut machine = Machine::with_memory(PagedMemory::new(&program));
+        machine.step().unwrap();
+        machine.set_loop_detection(true);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::InfiniteLoop);
+        assert_eq!(machine.memory().pages(), 2);
+    }
+}
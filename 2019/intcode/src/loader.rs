@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// Why a program's text didn't parse.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ProgramError {
+    // 1-based line and column of the problem, counting columns in characters.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+impl Error for ProgramError {}
+
+// Parses a comma-separated program, like a puzzle input. Whitespace and
+// newlines are allowed anywhere between numbers, and so is a trailing comma.
+pub fn parse_program(text: &str) -> Result<Vec<i64>, ProgramError> {
+    let mut program = vec![];
+    // Whether the last thing seen was a number, so a comma should come next.
+    let mut after_number = false;
+    for (line_index, line) in text.lines().enumerate() {
+        let mut chars = line.char_indices().enumerate().peekable();
+        while let Some((column_index, (start, c))) = chars.next() {
+            let error = |message: String| ProgramError {
+                line: line_index + 1,
+                column: column_index + 1,
+                message,
+            };
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == ',' {
+                if !after_number {
+                    return Err(error("expected a number before `,`".to_string()));
+                }
+                after_number = false;
+                continue;
+            }
+            if after_number {
+                return Err(error("expected `,` between numbers".to_string()));
+            }
+            let mut end = start + c.len_utf8();
+            while let Some(&(_, (i, c))) = chars.peek() {
+                if c == ',' || c.is_whitespace() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &line[start..end];
+            let value = word
+                .parse()
+                .map_err(|_| error(format!("invalid number `{}`", word)))?;
+            program.push(value);
+            after_number = true;
+        }
+    }
+    if program.is_empty() {
+        return Err(ProgramError {
+            line: 1,
+            column: 1,
+            message: "no program".to_string(),
+        });
+    }
+    Ok(program)
+}
+
+// Reads and parses a program. A malformed program is an
+// io::ErrorKind::InvalidData error wrapping a ProgramError.
+pub fn read_program(input: &mut dyn Read) -> io::Result<Vec<i64>> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    parse_program(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn load_program<P: AsRef<Path>>(path: P) -> io::Result<Vec<i64>> {
+    read_program(&mut fs::File::open(path)?)
+}
+
+// A program together with its program_hash, so results can be tied to the
+// input they came from.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LoadedProgram {
+    pub program: Vec<i64>,
+    pub hash: u64,
+}
+impl LoadedProgram {
+    pub fn new(program: Vec<i64>) -> LoadedProgram {
+        let hash = program_hash(&program);
+        LoadedProgram { program, hash }
+    }
+}
+
+// Like read_program, but also hashes what it read.
+pub fn read_program_with_hash(input: &mut dyn Read) -> io::Result<LoadedProgram> {
+    read_program(input).map(LoadedProgram::new)
+}
+
+pub fn load_program_with_hash<P: AsRef<Path>>(path: P) -> io::Result<LoadedProgram> {
+    load_program(path).map(LoadedProgram::new)
+}
+
+// A 64-bit FNV-1a hash of a program's values, for telling which input a
+// result came from. It doesn't depend on the program's formatting.
+pub fn program_hash(program: &[i64]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    program
+        .iter()
+        .flat_map(|val| val.to_le_bytes())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: usize, column: usize, message: &str) -> Result<Vec<i64>, ProgramError> {
+        Err(ProgramError {
+            line,
+            column,
+            message: message.to_string(),
+        })
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(parse_program("1,0,0,3,99\n"), Ok(vec![1, 0, 0, 3, 99]));
+        assert_eq!(
+            parse_program(" 1, -2 ,\n3,\r\n\t99,\n"),
+            Ok(vec![1, -2, 3, 99])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_program("1,2,\n3,x4,5"),
+            error(2, 3, "invalid number `x4`")
+        );
+        assert_eq!(
+            parse_program("1,,2"),
+            error(1, 3, "expected a number before `,`")
+        );
+        assert_eq!(
+            parse_program("1,2\n3"),
+            error(2, 1, "expected `,` between numbers")
+        );
+        assert_eq!(parse_program(" \n"), error(1, 1, "no program"));
+        let err = read_program(&mut "1,é".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 1, column 3: invalid number `é`");
+    }
+
+    #[test]
+    fn hash_ignores_formatting() {
+        let hash = program_hash(&parse_program("1,0,0,3,99").unwrap());
+        assert_eq!(
+            hash,
+            program_hash(&parse_program("1, 0, 0,\n3, 99,\n").unwrap())
+        );
+        assert_ne!(hash, program_hash(&[1, 0, 0, 3, 98]));
+        // The FNV-1a offset basis.
+        assert_eq!(program_hash(&[]), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn loaded_with_hash() {
+        let loaded = read_program_with_hash(&mut "1, 0, 0, 3, 99\n".as_bytes()).unwrap();
+        assert_eq!(loaded.program, vec![1, 0, 0, 3, 99]);
+        assert_eq!(loaded.hash, program_hash(&[1, 0, 0, 3, 99]));
+        assert_eq!(loaded, LoadedProgram::new(vec![1, 0, 0, 3, 99]));
+        assert_eq!(
+            read_program_with_hash(&mut "1,x".as_bytes())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
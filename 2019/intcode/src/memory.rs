@@ -0,0 +1,229 @@
+use super::ErrorCause;
+use std::collections::HashMap;
+
+// Where a Machine keeps its memory. Intcode memory is conceptually infinite
+// and zero-filled, so reads of cells that were never written return 0.
+pub trait Memory {
+    // The max_address a Machine using this backend starts with.
+    const DEFAULT_MAX_ADDRESS: usize;
+    fn read(&self, addr: usize) -> i64;
+    // Fails with AddressOutOfRange if memory can't be that long.
+    fn write(&mut self, addr: usize, val: i64) -> Result<(), ErrorCause>;
+    // One past the highest address that's been loaded or written. The
+    // instruction pointer can't run past this.
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // Forgets every address from `len` up, if memory is longer than that.
+    fn truncate(&mut self, len: usize);
+    // Replaces the whole contents of memory with `image`.
+    fn load_image(&mut self, image: &[i64]);
+    // The contents of addresses 0..len().
+    fn to_vec(&self) -> Vec<i64>;
+    // The address and value of every nonzero cell, in no particular order,
+    // without visiting cells the backend doesn't store.
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_>;
+}
+
+// The default: one contiguous vector that grows to cover the highest address
+// written. Fast, but a single write to a huge address allocates everything
+// below it, so machines using it stop at 16M words (128MB) unless told
+// otherwise.
+impl Memory for Vec<i64> {
+    const DEFAULT_MAX_ADDRESS: usize = (1 << 24) - 1;
+    fn read(&self, addr: usize) -> i64 {
+        self.get(addr).copied().unwrap_or(0)
+    }
+    fn write(&mut self, addr: usize, val: i64) -> Result<(), ErrorCause> {
+        if addr >= self.len() {
+            let len = addr
+                .checked_add(1)
+                .ok_or(ErrorCause::AddressOutOfRange(addr))?;
+            self.resize(len, 0);
+        }
+        self[addr] = val;
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+    fn load_image(&mut self, image: &[i64]) {
+        self.clear();
+        self.extend_from_slice(image);
+    }
+    fn to_vec(&self) -> Vec<i64> {
+        self.clone()
+    }
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        Box::new(
+            self.iter()
+                .enumerate()
+                .filter(|&(_, &val)| val != 0)
+                .map(|(addr, &val)| (addr, val)),
+        )
+    }
+}
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+// Memory that only allocates the fixed-size pages that hold nonzero values,
+// for programs that scatter writes across a large address space.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[i64; PAGE_SIZE]>>,
+    len: usize,
+}
+impl PagedMemory {
+    pub fn new(program: &[i64]) -> PagedMemory {
+        let mut memory = PagedMemory::default();
+        memory.load_image(program);
+        memory
+    }
+    // How many pages are allocated.
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+impl Memory for PagedMemory {
+    const DEFAULT_MAX_ADDRESS: usize = usize::MAX;
+    fn read(&self, addr: usize) -> i64 {
+        self.pages
+            .get(&(addr >> PAGE_BITS))
+            .map_or(0, |page| page[addr % PAGE_SIZE])
+    }
+    fn write(&mut self, addr: usize, val: i64) -> Result<(), ErrorCause> {
+        let end = addr
+            .checked_add(1)
+            .ok_or(ErrorCause::AddressOutOfRange(addr))?;
+        self.len = self.len.max(end);
+        let page = addr >> PAGE_BITS;
+        if val == 0 && !self.pages.contains_key(&page) {
+            return Ok(());
+        }
+        self.pages
+            .entry(page)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]))[addr % PAGE_SIZE] = val;
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.pages.retain(|&page, _| page << PAGE_BITS < len);
+        if let Some(page) = self.pages.get_mut(&(len >> PAGE_BITS)) {
+            for val in &mut page[len % PAGE_SIZE..] {
+                *val = 0;
+            }
+        }
+        self.len = len;
+    }
+    fn load_image(&mut self, image: &[i64]) {
+        self.pages.clear();
+        self.len = 0;
+        for (addr, &val) in image.iter().enumerate() {
+            self.write(addr, val)
+                .expect("image addresses fit in memory");
+        }
+    }
+    fn to_vec(&self) -> Vec<i64> {
+        (0..self.len).map(|addr| self.read(addr)).collect()
+    }
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        Box::new(self.pages.iter().flat_map(|(&page, cells)| {
+            cells
+                .iter()
+                .enumerate()
+                .filter(|&(_, &val)| val != 0)
+                .map(move |(offset, &val)| ((page << PAGE_BITS) + offset, val))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorCause, Machine, RunResult};
+
+    #[test]
+    fn paged_memory_reads_zero_until_written() {
+        let mut memory = PagedMemory::new(&[1, 0, 2]);
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.pages(), 1);
+        assert_eq!(memory.read(2), 2);
+        assert_eq!(memory.read(5000), 0);
+        memory.write(5000, 0).unwrap();
+        assert_eq!(memory.len(), 5001);
+        assert_eq!(memory.pages(), 1);
+        memory.write(PAGE_SIZE - 1, 7).unwrap();
+        memory.write(PAGE_SIZE, 8).unwrap();
+        assert_eq!(memory.pages(), 2);
+        assert_eq!(memory.read(PAGE_SIZE - 1), 7);
+        assert_eq!(memory.read(PAGE_SIZE), 8);
+        assert_eq!(memory.to_vec()[..3], [1, 0, 2]);
+        let mut nonzero: Vec<_> = memory.nonzero().collect();
+        nonzero.sort();
+        assert_eq!(
+            nonzero,
+            vec![(0, 1), (2, 2), (PAGE_SIZE - 1, 7), (PAGE_SIZE, 8)]
+        );
+        memory.truncate(PAGE_SIZE - 1);
+        assert_eq!(memory.len(), PAGE_SIZE - 1);
+        assert_eq!(memory.pages(), 1);
+        assert_eq!(memory.read(PAGE_SIZE - 1), 0);
+        assert_eq!(memory.read(PAGE_SIZE), 0);
+        assert_eq!(
+            memory.write(usize::MAX, 1),
+            Err(ErrorCause::AddressOutOfRange(usize::MAX))
+        );
+        assert_eq!(
+            vec![].write(usize::MAX, 1),
+            Err(ErrorCause::AddressOutOfRange(usize::MAX))
+        );
+    }
+
+    #[test]
+    fn far_writes_stay_small() {
+        // Stores 42 at 10^9 and reads it back.
+        let program = vec![1101, 40, 2, 1_000_000_000, 4, 1_000_000_000, 99];
+        let mut machine = Machine::with_memory(PagedMemory::new(&program));
+        assert_eq!(machine.run().unwrap(), RunResult::Output(42));
+        assert_eq!(machine.memory().pages(), 2);
+        assert_eq!(machine.memory().len(), 1_000_000_001);
+    }
+
+    #[test]
+    fn max_address() {
+        let program = vec![1101, 40, 2, 1000, 4, 1000, 99];
+        let mut machine = Machine::new(program.clone());
+        machine.set_max_address(999);
+        let err = machine.run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::AddressOutOfRange(1000));
+        assert_eq!(err.ip, 0);
+        assert_eq!(
+            err.to_string(),
+            "address 1000 out of range at 0 (instruction 1101)"
+        );
+        assert_eq!(machine.memory().len(), program.len());
+
+        let mut machine = Machine::new(program);
+        machine.set_max_address(1000);
+        assert_eq!(machine.run().unwrap(), RunResult::Output(42));
+        assert_eq!(
+            machine.write(1001, 1),
+            Err(ErrorCause::AddressOutOfRange(1001))
+        );
+
+        // Dense memory has a limit by default.
+        let far = 1 << 30;
+        let err = Machine::new(vec![1101, 40, 2, far, 99]).run().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::AddressOutOfRange(far as usize));
+    }
+}
@@ -0,0 +1,259 @@
+use super::{Flow, Hooks, Machine, MachineError, Memory, Outcome, Packet, Scheduler, Topology};
+
+// What a Monitor wants the network to do next.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Action {
+    Continue,
+    // Send a packet from the monitor's address.
+    Send { to: i64, payload: Vec<i64> },
+    Stop,
+}
+
+// Watches the packets sent to a Network's monitor address.
+pub trait Monitor {
+    fn receive(&mut self, _packet: &Packet) -> Action {
+        Action::Continue
+    }
+    // Called when no machine has sent or received anything for a whole
+    // round. If this returns Continue, nothing else will ever happen, so the
+    // network stops with NetworkStop::Idle.
+    fn idle(&mut self) -> Action;
+}
+
+// The day 23 NAT: remembers the last packet sent to it, and whenever the
+// network is idle, sends that packet's payload to address 0. It stops the
+// network instead when it would send the same Y value (the last word of the
+// payload) twice in a row.
+#[derive(Default)]
+pub struct Nat {
+    last: Option<Vec<i64>>,
+    last_sent: Option<Vec<i64>>,
+}
+impl Nat {
+    pub fn new() -> Nat {
+        Nat::default()
+    }
+    // The payload of the last packet sent to the NAT.
+    pub fn last(&self) -> Option<&[i64]> {
+        self.last.as_deref()
+    }
+}
+impl Monitor for Nat {
+    fn receive(&mut self, packet: &Packet) -> Action {
+        self.last = Some(packet.payload.clone());
+        Action::Continue
+    }
+    fn idle(&mut self) -> Action {
+        let payload = match &self.last {
+            Some(payload) => payload.clone(),
+            None => return Action::Continue,
+        };
+        if self.last_sent.as_ref().and_then(|p| p.last()) == payload.last() {
+            return Action::Stop;
+        }
+        self.last_sent = Some(payload.clone());
+        Action::Send { to: 0, payload }
+    }
+}
+
+// Why Network::run() stopped.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum NetworkStop {
+    // The monitor returned Action::Stop.
+    Monitor,
+    // The network went idle and the monitor had nothing to send.
+    Idle,
+    Halted,
+}
+
+// A packet-switched network of machines, as in day 23, run by a Scheduler
+// with addressed packets of three values: the destination address, then X
+// and Y. Machine i is given i as its first input. A machine that asks for
+// input when its queue is empty gets -1.
+pub struct Network<M: Memory = Vec<i64>> {
+    scheduler: Scheduler<M>,
+    monitor_address: usize,
+    log: Vec<Packet>,
+}
+
+const PACKET_SIZE: usize = 3;
+
+impl<M: Memory> Network<M> {
+    pub fn new(mut machines: Vec<Machine<M>>, monitor_address: usize) -> Network<M> {
+        for (address, machine) in machines.iter_mut().enumerate() {
+            machine.push_input(address as i64);
+        }
+        let topology = Topology::Addressed {
+            packet_size: PACKET_SIZE,
+        };
+        Network {
+            scheduler: Scheduler::new(machines, topology).expect("PACKET_SIZE is valid"),
+            monitor_address,
+            log: vec![],
+        }
+    }
+
+    pub fn machine(&self, address: usize) -> &Machine<M> {
+        self.scheduler.machine(address)
+    }
+
+    // Every packet sent so far, including ones from the monitor and ones
+    // sent to addresses with nothing there.
+    pub fn log(&self) -> &[Packet] {
+        &self.log
+    }
+
+    // Sends a packet from outside the network. It's logged as coming from
+    // the monitor.
+    pub fn send(&mut self, to: i64, payload: Vec<i64>) {
+        let packet = Packet {
+            from: self.monitor_address,
+            to,
+            payload,
+        };
+        self.log.push(packet.clone());
+        self.scheduler.send(packet);
+    }
+
+    pub fn run(&mut self, monitor: &mut dyn Monitor) -> Result<NetworkStop, MachineError> {
+        let mut hooks = MonitorHooks {
+            monitor,
+            address: self.monitor_address,
+            log: &mut self.log,
+        };
+        Ok(match self.scheduler.run_with(&mut hooks)? {
+            Outcome::Halted => NetworkStop::Halted,
+            Outcome::Idle => NetworkStop::Idle,
+            Outcome::Stopped => NetworkStop::Monitor,
+            Outcome::Deadlock(_) => unreachable!("starved machines are given -1"),
+        })
+    }
+}
+
+// Connects a Monitor to the Scheduler that runs a Network.
+struct MonitorHooks<'a> {
+    monitor: &'a mut dyn Monitor,
+    address: usize,
+    log: &'a mut Vec<Packet>,
+}
+impl MonitorHooks<'_> {
+    fn flow(&self, action: Action) -> Flow {
+        match action {
+            Action::Continue => Flow::Continue,
+            Action::Send { to, payload } => Flow::Send(Packet {
+                from: self.address,
+                to,
+                payload,
+            }),
+            Action::Stop => Flow::Stop,
+        }
+    }
+}
+impl Hooks for MonitorHooks<'_> {
+    fn packet(&mut self, packet: &Packet) -> Flow {
+        self.log.push(packet.clone());
+        if packet.to != self.address as i64 {
+            return Flow::Continue;
+        }
+        let action = self.monitor.receive(packet);
+        self.flow(action)
+    }
+    fn starved(&mut self, _machine: usize) -> Option<i64> {
+        Some(-1)
+    }
+    fn idle(&mut self) -> Flow {
+        let action = self.monitor.idle();
+        self.flow(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    // Each machine forwards the packets it receives to the next address,
+    // and the last one forwards them to the NAT at 255.
+    fn relay() -> Vec<i64> {
+        assemble(
+            "      in -> [me]
+                   add [me], #1 -> [next]
+                   eq [me], #2 -> [t]
+                   jf [t], #loop
+                   add #255, #0 -> [next]
+            loop:  in -> [x]
+                   eq [x], #-1 -> [t]
+                   jt [t], #loop
+                   in -> [y]
+                   out [next]
+                   out [x]
+                   out [y]
+                   jt #1, #loop
+            me:    data 0
+            next:  data 0
+            t:     data 0
+            x:     data 0
+            y:     data 0",
+        )
+        .unwrap()
+    }
+
+    fn packet(from: usize, to: i64, x: i64, y: i64) -> Packet {
+        Packet {
+            from,
+            to,
+            payload: vec![x, y],
+        }
+    }
+
+    #[test]
+    fn nat_resends_until_y_repeats() {
+        let machines = (0..3).map(|_| Machine::new(relay())).collect();
+        let mut network = Network::new(machines, 255);
+        network.send(0, vec![7, 8]);
+        let mut nat = Nat::new();
+        assert_eq!(network.run(&mut nat), Ok(NetworkStop::Monitor));
+        assert_eq!(nat.last(), Some(&[7, 8][..]));
+        let round = [packet(0, 1, 7, 8), packet(1, 2, 7, 8), packet(2, 255, 7, 8)];
+        let mut expected = vec![packet(255, 0, 7, 8)];
+        expected.extend(round.iter().cloned());
+        expected.push(packet(255, 0, 7, 8));
+        expected.extend(round.iter().cloned());
+        assert_eq!(network.log(), &expected[..]);
+    }
+
+    #[test]
+    fn idle_without_traffic() {
+        let machines = (0..3).map(|_| Machine::new(relay())).collect();
+        let mut network = Network::new(machines, 255);
+        assert_eq!(network.run(&mut Nat::new()), Ok(NetworkStop::Idle));
+        assert_eq!(network.log(), &[]);
+    }
+
+    #[test]
+    fn monitor_can_stop_on_receive() {
+        struct FirstPacket(Option<Packet>);
+        impl Monitor for FirstPacket {
+            fn receive(&mut self, packet: &Packet) -> Action {
+                self.0 = Some(packet.clone());
+                Action::Stop
+            }
+            fn idle(&mut self) -> Action {
+                Action::Continue
+            }
+        }
+        let machines = (0..3).map(|_| Machine::new(relay())).collect();
+        let mut network = Network::new(machines, 255);
+        network.send(1, vec![1, 2]);
+        let mut monitor = FirstPacket(None);
+        assert_eq!(network.run(&mut monitor), Ok(NetworkStop::Monitor));
+        assert_eq!(monitor.0, Some(packet(2, 255, 1, 2)));
+    }
+
+    #[test]
+    fn halted_network() {
+        let machines = (0..2).map(|_| Machine::new(vec![3, 0, 99])).collect();
+        let mut network = Network::new(machines, 255);
+        assert_eq!(network.run(&mut Nat::new()), Ok(NetworkStop::Halted));
+    }
+}
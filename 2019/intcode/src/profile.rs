@@ -0,0 +1,231 @@
+use super::disasm::decode_at;
+use super::{disassemble_at, Opcode, TraceStep, Tracer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+// A loop found by watching backward jumps: the jump at `end` went back to
+// `start` `iterations` times.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct HotLoop {
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+}
+
+// A Tracer that counts what a program does: how often each address and each
+// opcode ran, and how often each jump was taken.
+#[derive(Default)]
+pub struct Profiler {
+    hits: BTreeMap<usize, u64>,
+    opcodes: Vec<(Opcode, u64)>,
+    total: u64,
+    // Counts of taken jumps, by jump address and target.
+    taken: BTreeMap<(usize, usize), u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // How many times the instruction at `address` ran.
+    pub fn hits(&self, address: usize) -> u64 {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    // Every executed address with its count, in address order.
+    pub fn address_counts(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.hits.iter().map(|(&address, &count)| (address, count))
+    }
+
+    // The count for each opcode that ran at least once, in order of their
+    // codes.
+    pub fn opcode_counts(&self) -> Vec<(Opcode, u64)> {
+        let mut counts = self.opcodes.clone();
+        counts.sort_by_key(|(opcode, _)| opcode.code());
+        counts
+    }
+
+    // How many times the jump at `address` was taken.
+    pub fn taken(&self, address: usize) -> u64 {
+        self.taken
+            .range((address, 0)..=(address, usize::MAX))
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    // Loops closed by backward jumps, most iterations first.
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = self
+            .taken
+            .iter()
+            .filter(|((from, to), _)| to <= from)
+            .map(|(&(end, start), &iterations)| HotLoop {
+                start,
+                end,
+                iterations,
+            })
+            .collect();
+        loops.sort_by_key(|l| (std::cmp::Reverse(l.iterations), l.start));
+        loops
+    }
+
+    // A disassembly of `memory` with each line prefixed by how many times it
+    // ran, or `-` if it never did. Jumps also show how often they were taken.
+    // Pass the program as it was when it ran: for self-modifying programs,
+    // the listing shows whatever instructions are in `memory`.
+    pub fn coverage_report(&self, memory: &[i64]) -> String {
+        let mut report = String::new();
+        let mut address = 0;
+        while let Some(mut line) = disassemble_at(memory, address) {
+            // Don't let a line swallow an address that ran as an instruction.
+            if (address + 1..address + line.len).any(|a| self.hits.contains_key(&a)) {
+                line.len = 1;
+                line.text = format!("DATA {}", memory[address]);
+            }
+            let hits = self.hits(address);
+            let count = if hits == 0 {
+                "-".to_string()
+            } else {
+                hits.to_string()
+            };
+            write!(report, "{:>10}  {}", count, line).unwrap();
+            let is_jump = matches!(
+                decode_at(memory, address),
+                Some((Opcode::JumpIfTrue, _, _)) | Some((Opcode::JumpIfFalse, _, _))
+            );
+            if hits > 0 && line.len > 1 && is_jump {
+                write!(report, "  ; taken {}/{}", self.taken(address), hits).unwrap();
+            }
+            report.push('\n');
+            address += line.len;
+        }
+        report
+    }
+
+    // The total, opcode counts, and hottest loops, for printing after a run.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} instructions executed\n", self.total);
+        for (opcode, count) in self.opcode_counts() {
+            writeln!(summary, "{:>10}  {}", count, opcode.mnemonic()).unwrap();
+        }
+        for l in self.hot_loops().iter().take(10) {
+            writeln!(
+                summary,
+                "loop {:04}..={:04}: {} iterations",
+                l.start, l.end, l.iterations
+            )
+            .unwrap();
+        }
+        summary
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, step: &TraceStep) {
+        *self.hits.entry(step.ip).or_insert(0) += 1;
+        match self.opcodes.iter_mut().find(|(o, _)| *o == step.opcode) {
+            Some((_, count)) => *count += 1,
+            None => self.opcodes.push((step.opcode, 1)),
+        }
+        self.total += 1;
+        // Whether a jump is taken depends only on its condition, so a jump to
+        // the next instruction still counts.
+        let taken = match (step.opcode, step.operands.first()) {
+            (Opcode::JumpIfTrue, Some(&condition)) => condition != 0,
+            (Opcode::JumpIfFalse, Some(&condition)) => condition == 0,
+            _ => false,
+        };
+        if taken {
+            if let Some(Ok(target)) = step.operands.get(1).map(|&t| usize::try_from(t)) {
+                *self.taken.entry((step.ip, target)).or_insert(0) += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, run_intcode_traced, VecState};
+
+    // Outputs its input, counting down to 1, and then outputs 0 if the input
+    // was even.
+    fn countdown() -> Vec<i64> {
+        assemble(
+            "      in -> [n]
+            loop:  out [n]
+                   add [n], #-1 -> [n]
+                   mul [odd], #-1 -> [odd]
+                   add [odd], #1 -> [odd]
+                   jt [n], #loop
+                   jt [odd], #done
+                   out #0
+            done:  hlt
+            n:     data 0
+            odd:   data 0",
+        )
+        .unwrap()
+    }
+
+    fn profile(input: i64) -> Profiler {
+        let mut profiler = Profiler::new();
+        run_intcode_traced(countdown(), &mut VecState::new(vec![input]), &mut profiler).unwrap();
+        profiler
+    }
+
+    #[test]
+    fn counts() {
+        let profiler = profile(3);
+        assert_eq!(profiler.hits(0), 1);
+        assert_eq!(profiler.hits(2), 3);
+        assert_eq!(profiler.hits(22), 0);
+        assert_eq!(profiler.total(), 1 + 3 * 5 + 2);
+        assert_eq!(profiler.taken(16), 2);
+        assert_eq!(profiler.taken(19), 1);
+        assert_eq!(
+            profiler.hot_loops(),
+            vec![HotLoop {
+                start: 2,
+                end: 16,
+                iterations: 2
+            }]
+        );
+        let outs = profiler
+            .opcode_counts()
+            .into_iter()
+            .find(|&(opcode, _)| opcode == Opcode::Out);
+        assert_eq!(outs, Some((Opcode::Out, 3)));
+    }
+
+    #[test]
+    fn coverage_differs_by_input() {
+        let odd = profile(3).coverage_report(&countdown());
+        let even = profile(2).coverage_report(&countdown());
+        assert!(odd.contains("         3  0016: JT [25], #2  ; taken 2/3\n"));
+        assert!(odd.contains("         -  0022: OUT #0\n"));
+        assert!(even.contains("         1  0022: OUT #0\n"));
+        assert!(even.contains("         -  0025: DATA 0\n"));
+    }
+
+    #[test]
+    fn jump_to_next_instruction() {
+        let program = assemble(
+            "      jt #1, #next
+            next:  jf #1, #done
+                   jf #0, #done
+            done:  hlt",
+        )
+        .unwrap();
+        let mut profiler = Profiler::new();
+        run_intcode_traced(program, &mut VecState::new(vec![]), &mut profiler).unwrap();
+        assert_eq!(profiler.taken(0), 1);
+        assert_eq!(profiler.taken(3), 0);
+        assert_eq!(profiler.taken(6), 1);
+    }
+}
@@ -0,0 +1,374 @@
+use super::{Device, ErrorCause, IntcodeError, Machine, Memory, Output, RunResult};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Event {
+    Input(i64),
+    Output(i64),
+    Halt,
+}
+
+// Something that happened during a recorded run, and how many instructions
+// the machine had completed when it happened. An input is counted before the
+// input instruction completes, and an output or halt after.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Entry {
+    pub instruction: u64,
+    pub event: Event,
+}
+
+// The inputs and outputs of a run, in order. Recordings are written as text,
+// one event per line, with instruction counts stored as the difference from
+// the previous event:
+//
+//   i 2 5
+//   o 14 1
+//   h 1
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Recording {
+    // Always in instruction order.
+    entries: Vec<Entry>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // If the machine's count went backwards, say because it was restored
+    // from a snapshot partway through, the event is recorded as happening
+    // at the same instruction as the one before it.
+    fn push(&mut self, instruction: u64, event: Event) {
+        let last = self.entries.last().map_or(0, |entry| entry.instruction);
+        self.entries.push(Entry {
+            instruction: instruction.max(last),
+            event,
+        });
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut last = 0;
+        for entry in &self.entries {
+            let delta = entry.instruction - last;
+            last = entry.instruction;
+            match entry.event {
+                Event::Input(val) => writeln!(f, "i {} {}", delta, val)?,
+                Event::Output(val) => writeln!(f, "o {} {}", delta, val)?,
+                Event::Halt => writeln!(f, "h {}", delta)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ParseRecordingError {
+    // 1-based line number in the recording.
+    pub line: usize,
+    pub message: String,
+}
+impl fmt::Display for ParseRecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+impl Error for ParseRecordingError {}
+
+impl FromStr for Recording {
+    type Err = ParseRecordingError;
+    fn from_str(s: &str) -> Result<Recording, ParseRecordingError> {
+        let mut recording = Recording::new();
+        let mut instruction: u64 = 0;
+        for (index, line) in s.lines().enumerate() {
+            let error = |message: String| ParseRecordingError {
+                line: index + 1,
+                message,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let number = |i: usize| -> Result<i64, ParseRecordingError> {
+                let word = words
+                    .get(i)
+                    .ok_or_else(|| error(format!("missing field in `{}`", line)))?;
+                word.parse()
+                    .map_err(|_| error(format!("invalid number `{}`", word)))
+            };
+            let delta = number(1)?;
+            if delta < 0 {
+                return Err(error(format!("negative instruction count `{}`", delta)));
+            }
+            instruction = instruction
+                .checked_add(delta as u64)
+                .ok_or_else(|| error("instruction count overflows".to_string()))?;
+            let (event, len) = match words[0] {
+                "i" => (Event::Input(number(2)?), 3),
+                "o" => (Event::Output(number(2)?), 3),
+                "h" => (Event::Halt, 2),
+                kind => return Err(error(format!("unknown event `{}`", kind))),
+            };
+            if words.len() > len {
+                return Err(error(format!("extra fields in `{}`", line)));
+            }
+            recording.push(instruction, event);
+        }
+        Ok(recording)
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    // Like run_device(), but appends every input, output, and halt to
+    // `recording`. Instruction counts come from instructions_executed(), so
+    // a replay should start from a machine in the same state as this one.
+    pub fn run_recorded(
+        &mut self,
+        device: &mut dyn Device,
+        recording: &mut Recording,
+    ) -> Result<RunResult, IntcodeError> {
+        loop {
+            match self.run()? {
+                RunResult::NeedsInput => match device.input() {
+                    Some(val) => {
+                        recording.push(self.executed, Event::Input(val));
+                        self.push_input(val);
+                    }
+                    None => return Err(self.error(ErrorCause::MissingInput)),
+                },
+                RunResult::Output(val) => {
+                    recording.push(self.executed, Event::Output(val));
+                    device.output(val);
+                }
+                RunResult::Halted => {
+                    recording.push(self.executed, Event::Halt);
+                    return Ok(RunResult::Halted);
+                }
+                RunResult::BudgetExhausted => return Ok(RunResult::BudgetExhausted),
+            }
+        }
+    }
+}
+
+// The first point where a replay didn't match its recording.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Divergence {
+    // The index of the recording entry that didn't match.
+    pub index: usize,
+    // That entry, or None if the replay ran past the end of the recording.
+    pub expected: Option<Entry>,
+    // What the machine did instead, and its instruction count at the time.
+    // This is NeedsInput, Output, or Halted.
+    pub actual: (u64, RunResult),
+}
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay diverged at event {}: expected ", self.index)?;
+        match self.expected {
+            Some(Entry { instruction, event }) => {
+                write!(f, "{:?} at instruction {}", event, instruction)?
+            }
+            None => write!(f, "the end of the recording")?,
+        }
+        write!(
+            f,
+            ", got {:?} at instruction {}",
+            self.actual.1, self.actual.0
+        )
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ReplayError {
+    Intcode(IntcodeError),
+    Diverged(Divergence),
+}
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Intcode(err) => err.fmt(f),
+            ReplayError::Diverged(divergence) => divergence.fmt(f),
+        }
+    }
+}
+impl Error for ReplayError {}
+impl From<IntcodeError> for ReplayError {
+    fn from(err: IntcodeError) -> ReplayError {
+        ReplayError::Intcode(err)
+    }
+}
+
+// Replays a Recording into a machine: each input comes from the recording
+// instead of a device, and each output, and the instruction count of every
+// event, is checked against it.
+pub struct Replay<'a> {
+    recording: &'a Recording,
+    next: usize,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(recording: &'a Recording) -> Replay<'a> {
+        Replay { recording, next: 0 }
+    }
+
+    // How many entries of the recording have been matched so far.
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    // Runs `machine` until it halts or does something the recording doesn't,
+    // sending each output to `output`. If the recording ends while the
+    // machine is waiting for input, returns NeedsInput. Can be called again
+    // after BudgetExhausted.
+    pub fn run<M: Memory>(
+        &mut self,
+        machine: &mut Machine<M>,
+        output: &mut dyn Output,
+    ) -> Result<RunResult, ReplayError> {
+        loop {
+            let result = machine.run()?;
+            if result == RunResult::BudgetExhausted {
+                return Ok(result);
+            }
+            let instruction = machine.instructions_executed();
+            let expected = self.recording.entries.get(self.next).copied();
+            let matches = match (expected, result) {
+                (Some(entry), _) if entry.instruction != instruction => false,
+                (
+                    Some(Entry {
+                        event: Event::Input(val),
+                        ..
+                    }),
+                    RunResult::NeedsInput,
+                ) => {
+                    machine.push_input(val);
+                    true
+                }
+                (Some(Entry { event, .. }), RunResult::Output(val)) => event == Event::Output(val),
+                (Some(Entry { event, .. }), RunResult::Halted) => event == Event::Halt,
+                (None, RunResult::NeedsInput) => return Ok(RunResult::NeedsInput),
+                _ => false,
+            };
+            if !matches {
+                return Err(ReplayError::Diverged(Divergence {
+                    index: self.next,
+                    expected,
+                    actual: (instruction, result),
+                }));
+            }
+            self.next += 1;
+            match result {
+                RunResult::Output(val) => output.output(val),
+                RunResult::Halted => return Ok(result),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Io, Machine};
+
+    // Outputs each input plus `step` until it reads 0.
+    fn increment(step: i64) -> Vec<i64> {
+        assemble(&format!(
+            "loop:  in -> [x]
+                   jf [x], #done
+                   add [x], #{} -> [x]
+                   out [x]
+                   jt #1, #loop
+            done:  hlt
+            x:     data 0",
+            step
+        ))
+        .unwrap()
+    }
+
+    fn record(inputs: Vec<i64>) -> Recording {
+        let mut recording = Recording::new();
+        let mut io = Io {
+            input: inputs.into_iter(),
+            output: vec![],
+        };
+        Machine::new(increment(1))
+            .run_recorded(&mut io, &mut recording)
+            .unwrap();
+        recording
+    }
+
+    #[test]
+    fn text_format() {
+        let recording = record(vec![5, 7, 0]);
+        let text = recording.to_string();
+        assert_eq!(text, "i 0 5\no 4 6\ni 1 7\no 4 8\ni 1 0\nh 3\n");
+        assert_eq!(text.parse(), Ok(recording));
+        assert_eq!(
+            "i 0 5\nx 1".parse::<Recording>(),
+            Err(ParseRecordingError {
+                line: 2,
+                message: "unknown event `x`".to_string()
+            })
+        );
+        let huge = format!("h {}\n", i64::MAX);
+        assert_eq!(
+            huge.repeat(3).parse::<Recording>(),
+            Err(ParseRecordingError {
+                line: 3,
+                message: "instruction count overflows".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn entries_stay_in_order() {
+        let mut recording = Recording::new();
+        recording.push(5, Event::Output(1));
+        recording.push(2, Event::Halt);
+        assert_eq!(recording.entries()[1].instruction, 5);
+        assert_eq!(recording.to_string(), "o 5 1\nh 0\n");
+    }
+
+    #[test]
+    fn replay_without_device() {
+        let recording = record(vec![5, 7, 0]);
+        let mut outputs = vec![];
+        let mut replay = Replay::new(&recording);
+        let result = replay.run(&mut Machine::new(increment(1)), &mut outputs);
+        assert_eq!(result, Ok(RunResult::Halted));
+        assert_eq!(outputs, vec![6, 8]);
+
+        // A recording that stops early leaves the machine waiting for input.
+        let mut partial = recording.clone();
+        partial.entries.truncate(2);
+        let mut replay = Replay::new(&partial);
+        let result = replay.run(&mut Machine::new(increment(1)), &mut vec![]);
+        assert_eq!(result, Ok(RunResult::NeedsInput));
+        assert_eq!(replay.position(), 2);
+    }
+
+    #[test]
+    fn divergence() {
+        let recording = record(vec![5, 7, 0]);
+        let mut outputs = vec![];
+        let result = Replay::new(&recording).run(&mut Machine::new(increment(2)), &mut outputs);
+        let divergence = Divergence {
+            index: 1,
+            expected: Some(Entry {
+                instruction: 4,
+                event: Event::Output(6),
+            }),
+            actual: (4, RunResult::Output(7)),
+        };
+        assert_eq!(result, Err(ReplayError::Diverged(divergence)));
+        assert_eq!(outputs, vec![]);
+    }
+}
@@ -0,0 +1,509 @@
+use super::{IntcodeError, Machine, Memory, RunResult};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+// How a Scheduler routes each machine's outputs.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Topology {
+    // Machine i's outputs become machine i+1's inputs. The last machine's
+    // outputs leave the network.
+    Pipeline,
+    // Like Pipeline, but the last machine's outputs go back to machine 0.
+    Ring,
+    // Machines send packets of `packet_size` outputs: a destination address
+    // followed by the payload, which is queued as input to the machine with
+    // that index. Packets for other addresses leave the network.
+    Addressed { packet_size: usize },
+}
+
+// Values sent by one machine, as a unit. Under Pipeline and Ring, each output
+// is its own packet.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Packet {
+    pub from: usize,
+    pub to: i64,
+    pub payload: Vec<i64>,
+}
+
+// Why Scheduler::run() stopped.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Outcome {
+    Halted,
+    // None of the listed machines can continue until they get input, and
+    // all the other machines have halted.
+    Deadlock(Vec<usize>),
+    // A whole round went by in which no machine sent a packet or had any
+    // input but what Hooks::starved() gave it, and Hooks::idle() had nothing
+    // to send.
+    Idle,
+    // A hook returned Flow::Stop.
+    Stopped,
+}
+
+// What a hook wants the scheduler to do next.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Flow {
+    Continue,
+    // Route this packet as if a machine had sent it.
+    Send(Packet),
+    Stop,
+}
+
+// Lets the caller of Scheduler::run_with() watch packets and step in when
+// machines run out of input. The defaults leave the run as run() does it.
+pub trait Hooks {
+    // Called with every packet, before it's delivered.
+    fn packet(&mut self, _packet: &Packet) -> Flow {
+        Flow::Continue
+    }
+    // Called at the start of each round for each machine that's waiting for
+    // input with none queued. Returning a value gives the machine that input
+    // and runs it, rather than leaving it blocked.
+    fn starved(&mut self, _machine: usize) -> Option<i64> {
+        None
+    }
+    // Called when a round was idle. Continue ends the run with Outcome::Idle.
+    fn idle(&mut self) -> Flow {
+        Flow::Continue
+    }
+}
+
+struct NoHooks;
+impl Hooks for NoHooks {}
+
+// Scheduler::new() was given a topology it can't route.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct InvalidTopology(pub Topology);
+impl fmt::Display for InvalidTopology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid topology {:?}: packets need room for an address",
+            self.0
+        )
+    }
+}
+impl Error for InvalidTopology {}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MachineError {
+    // The index of the machine that failed.
+    pub machine: usize,
+    pub error: IntcodeError,
+}
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "machine {}: {}", self.machine, self.error)
+    }
+}
+impl Error for MachineError {}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Status {
+    Runnable,
+    WaitingForInput,
+    Halted,
+}
+
+// How a machine's turn went.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Turn {
+    // It sent a packet, or ran out of budget before it could finish.
+    Active,
+    Quiet,
+    // A hook asked to stop.
+    Stopped,
+}
+
+// How many instructions a machine without its own budget gets per turn.
+pub const TURN_BUDGET: u64 = 10_000;
+
+// Runs several machines on one thread, taking turns in index order. Each turn
+// runs a machine until it halts, waits for input, or uses up its instruction
+// budget, delivering its outputs as it produces them, so the interleaving is
+// deterministic.
+pub struct Scheduler<M: Memory = Vec<i64>> {
+    machines: Vec<Machine<M>>,
+    status: Vec<Status>,
+    topology: Topology,
+    // Outputs of each machine that don't yet make up a whole packet.
+    partial: Vec<Vec<i64>>,
+    output: Vec<Packet>,
+    last_output: Option<(usize, i64)>,
+}
+
+impl<M: Memory> Scheduler<M> {
+    // Machines that don't have an instruction budget are given TURN_BUDGET,
+    // so one that never blocks can't keep the others from running.
+    pub fn new(
+        mut machines: Vec<Machine<M>>,
+        topology: Topology,
+    ) -> Result<Scheduler<M>, InvalidTopology> {
+        if let Topology::Addressed { packet_size: 0 } = topology {
+            return Err(InvalidTopology(topology));
+        }
+        for machine in &mut machines {
+            if machine.instruction_budget().is_none() {
+                machine.set_instruction_budget(Some(TURN_BUDGET));
+            }
+        }
+        let n = machines.len();
+        Ok(Scheduler {
+            machines,
+            status: vec![Status::Runnable; n],
+            topology,
+            partial: vec![vec![]; n],
+            output: vec![],
+            last_output: None,
+        })
+    }
+
+    pub fn machine(&self, index: usize) -> &Machine<M> {
+        &self.machines[index]
+    }
+
+    pub fn push_input(&mut self, index: usize, val: i64) {
+        self.deliver(index, val);
+    }
+
+    // Routes a packet from outside the network, without showing it to any
+    // hooks.
+    pub fn send(&mut self, packet: Packet) {
+        self.route(packet);
+    }
+
+    // Packets that were sent outside the network, in the order they were
+    // sent.
+    pub fn output(&self) -> &[Packet] {
+        &self.output
+    }
+
+    // The most recent value any machine produced, and which machine
+    // produced it.
+    pub fn last_output(&self) -> Option<(usize, i64)> {
+        self.last_output
+    }
+
+    // Runs machines in turn until they've all halted or none of them can
+    // make progress.
+    pub fn run(&mut self) -> Result<Outcome, MachineError> {
+        self.run_with(&mut NoHooks)
+    }
+
+    // Like run(), but shows each packet to `hooks` and lets them feed
+    // machines that are waiting for input.
+    pub fn run_with(&mut self, hooks: &mut dyn Hooks) -> Result<Outcome, MachineError> {
+        loop {
+            let mut ran = false;
+            let mut progressed = false;
+            for index in 0..self.machines.len() {
+                match self.status[index] {
+                    Status::Runnable => progressed = true,
+                    Status::WaitingForInput => match hooks.starved(index) {
+                        Some(val) => self.machines[index].push_input(val),
+                        None => continue,
+                    },
+                    Status::Halted => continue,
+                }
+                ran = true;
+                match self.run_machine(index, hooks)? {
+                    Turn::Active => progressed = true,
+                    Turn::Quiet => {}
+                    Turn::Stopped => return Ok(Outcome::Stopped),
+                }
+            }
+            if !ran {
+                let waiting: Vec<usize> = (0..self.machines.len())
+                    .filter(|&i| self.status[i] == Status::WaitingForInput)
+                    .collect();
+                if waiting.is_empty() {
+                    return Ok(Outcome::Halted);
+                }
+                return Ok(Outcome::Deadlock(waiting));
+            }
+            if !progressed {
+                match hooks.idle() {
+                    Flow::Continue => return Ok(Outcome::Idle),
+                    Flow::Stop => return Ok(Outcome::Stopped),
+                    Flow::Send(packet) => {
+                        if self.dispatch(packet, hooks) {
+                            return Ok(Outcome::Stopped);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn run_machine(&mut self, index: usize, hooks: &mut dyn Hooks) -> Result<Turn, MachineError> {
+        let mut turn = Turn::Quiet;
+        loop {
+            let result = self.machines[index].run().map_err(|error| MachineError {
+                machine: index,
+                error,
+            })?;
+            match result {
+                RunResult::Output(val) => {
+                    if let Some(packet) = self.packetize(index, val) {
+                        turn = Turn::Active;
+                        if self.dispatch(packet, hooks) {
+                            return Ok(Turn::Stopped);
+                        }
+                    }
+                }
+                RunResult::NeedsInput => {
+                    self.status[index] = Status::WaitingForInput;
+                    return Ok(turn);
+                }
+                RunResult::Halted => {
+                    self.status[index] = Status::Halted;
+                    return Ok(turn);
+                }
+                // Let the other machines have a turn.
+                RunResult::BudgetExhausted => return Ok(Turn::Active),
+            }
+        }
+    }
+
+    // Returns the packet that `val` completes, if any.
+    fn packetize(&mut self, from: usize, val: i64) -> Option<Packet> {
+        self.last_output = Some((from, val));
+        let n = self.machines.len();
+        Some(match self.topology {
+            Topology::Pipeline => Packet {
+                from,
+                to: from as i64 + 1,
+                payload: vec![val],
+            },
+            Topology::Ring => Packet {
+                from,
+                to: ((from + 1) % n) as i64,
+                payload: vec![val],
+            },
+            Topology::Addressed { packet_size } => {
+                self.partial[from].push(val);
+                if self.partial[from].len() < packet_size {
+                    return None;
+                }
+                let mut payload = std::mem::take(&mut self.partial[from]);
+                let to = payload.remove(0);
+                Packet { from, to, payload }
+            }
+        })
+    }
+
+    // Shows `packet` to the hooks and routes it, then does the same for any
+    // packet they send in response. Returns true if they asked to stop.
+    fn dispatch(&mut self, mut packet: Packet, hooks: &mut dyn Hooks) -> bool {
+        loop {
+            let flow = hooks.packet(&packet);
+            self.route(packet);
+            match flow {
+                Flow::Continue => return false,
+                Flow::Send(next) => packet = next,
+                Flow::Stop => return true,
+            }
+        }
+    }
+
+    fn route(&mut self, packet: Packet) {
+        match usize::try_from(packet.to) {
+            Ok(to) if to < self.machines.len() => {
+                for &val in &packet.payload {
+                    self.deliver(to, val);
+                }
+            }
+            _ => self.output.push(packet),
+        }
+    }
+
+    fn deliver(&mut self, index: usize, val: i64) {
+        self.machines[index].push_input(val);
+        if self.status[index] == Status::WaitingForInput {
+            self.status[index] = Status::Runnable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn amplifiers(program: &[i64], phases: &[i64], topology: Topology) -> Scheduler {
+        let machines = phases
+            .iter()
+            .map(|&phase| {
+                let mut machine = Machine::new(program.to_vec());
+                machine.push_input(phase);
+                machine
+            })
+            .collect();
+        let mut scheduler = Scheduler::new(machines, topology).unwrap();
+        scheduler.push_input(0, 0);
+        scheduler
+    }
+
+    #[test]
+    fn pipeline() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let mut scheduler = amplifiers(&program, &[4, 3, 2, 1, 0], Topology::Pipeline);
+        assert_eq!(scheduler.run(), Ok(Outcome::Halted));
+        assert_eq!(
+            scheduler.output(),
+            &[Packet {
+                from: 4,
+                to: 5,
+                payload: vec![43210]
+            }]
+        );
+        assert_eq!(scheduler.last_output(), Some((4, 43210)));
+    }
+
+    #[test]
+    fn ring() {
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut scheduler = amplifiers(&program, &[9, 8, 7, 6, 5], Topology::Ring);
+        assert_eq!(scheduler.run(), Ok(Outcome::Halted));
+        assert_eq!(scheduler.output(), &[]);
+        assert_eq!(scheduler.last_output(), Some((4, 139629729)));
+    }
+
+    #[test]
+    fn addressed_packets() {
+        // Each machine reads its address, then sends (address + 1) * 10 to
+        // the next machine, except the last, which sends to address 99.
+        // Then it echoes one received value to the outside.
+        let program = assemble(
+            "      in -> [me]
+                   add [me], #1 -> [next]
+                   mul [next], #10 -> [val]
+                   eq [me], #2 -> [last]
+                   jf [last], #send
+                   add #99, #0 -> [next]
+            send:  out [next]
+                   out [val]
+                   in -> [val]
+                   out #-1
+                   out [val]
+                   hlt
+            me:    data 0
+            next:  data 0
+            val:   data 0
+            last:  data 0",
+        )
+        .unwrap();
+        let machines = (0..3)
+            .map(|i| {
+                let mut machine = Machine::new(program.clone());
+                machine.push_input(i);
+                machine
+            })
+            .collect();
+        let mut scheduler =
+            Scheduler::new(machines, Topology::Addressed { packet_size: 2 }).unwrap();
+        assert_eq!(scheduler.run(), Ok(Outcome::Deadlock(vec![0])));
+        let packet = |from, to, val| Packet {
+            from,
+            to,
+            payload: vec![val],
+        };
+        assert_eq!(
+            scheduler.output(),
+            &[packet(1, -1, 10), packet(2, 99, 30), packet(2, -1, 20)]
+        );
+        assert_eq!(scheduler.last_output(), Some((2, 20)));
+
+        scheduler.push_input(0, 5);
+        assert_eq!(scheduler.run(), Ok(Outcome::Halted));
+        assert_eq!(scheduler.output()[3], packet(0, -1, 5));
+    }
+
+    #[test]
+    fn hooks_feed_and_stop() {
+        struct Feed(i64);
+        impl Hooks for Feed {
+            fn starved(&mut self, _machine: usize) -> Option<i64> {
+                Some(self.0)
+            }
+            fn packet(&mut self, _packet: &Packet) -> Flow {
+                Flow::Stop
+            }
+        }
+        let program = assemble(
+            "   in -> [x]
+                mul [x], #2 -> [x]
+                out [x]
+                hlt
+             x: data 0",
+        )
+        .unwrap();
+        let mut scheduler =
+            Scheduler::new(vec![Machine::new(program)], Topology::Pipeline).unwrap();
+        assert_eq!(scheduler.run_with(&mut Feed(21)), Ok(Outcome::Stopped));
+        // The packet that stopped the run is still delivered.
+        assert_eq!(
+            scheduler.output(),
+            &[Packet {
+                from: 0,
+                to: 1,
+                payload: vec![42]
+            }]
+        );
+
+        // A machine that only ever gets input from starved() is idle.
+        let machines = vec![Machine::new(vec![3, 5, 1105, 1, 0, 0])];
+        let mut scheduler = Scheduler::new(machines, Topology::Pipeline).unwrap();
+        assert_eq!(scheduler.run_with(&mut Feed(-1)), Ok(Outcome::Idle));
+    }
+
+    #[test]
+    fn packets_need_an_address() {
+        let topology = Topology::Addressed { packet_size: 0 };
+        let err = Scheduler::new(vec![Machine::new(vec![99])], topology)
+            .err()
+            .unwrap();
+        assert_eq!(err, InvalidTopology(topology));
+    }
+
+    #[test]
+    fn busy_machines_take_turns() {
+        // Machine 0 counts forever and never blocks; machine 1 still gets
+        // to send its output.
+        let spin = assemble(
+            "loop: add [n], #1 -> [n]
+                   jt #1, #loop
+             n:    data 0",
+        )
+        .unwrap();
+        let machines = vec![Machine::new(spin), Machine::new(vec![104, 7, 99])];
+        let mut scheduler = Scheduler::new(machines, Topology::Pipeline).unwrap();
+        struct StopOnPacket;
+        impl Hooks for StopOnPacket {
+            fn packet(&mut self, _packet: &Packet) -> Flow {
+                Flow::Stop
+            }
+        }
+        assert_eq!(scheduler.run_with(&mut StopOnPacket), Ok(Outcome::Stopped));
+        assert_eq!(scheduler.last_output(), Some((1, 7)));
+        assert_eq!(scheduler.machine(0).read(7), TURN_BUDGET as i64 / 2);
+    }
+
+    #[test]
+    fn errors_name_the_machine() {
+        let machines = vec![Machine::new(vec![4, 0, 99]), Machine::new(vec![3, 0, 42])];
+        let mut scheduler = Scheduler::new(machines, Topology::Pipeline).unwrap();
+        let err = scheduler.run().unwrap_err();
+        assert_eq!(err.machine, 1);
+        assert_eq!(
+            err.to_string(),
+            "machine 1: unknown opcode 42 at 2 (instruction 42)"
+        );
+    }
+}
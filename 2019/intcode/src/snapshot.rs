@@ -0,0 +1,262 @@
+use super::{Machine, Memory};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+// Everything needed to resume a Machine exactly where it was.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Snapshot {
+    pub memory: Vec<i64>,
+    pub ip: usize,
+    pub relative_base: i64,
+    // Input that was pushed but not yet read.
+    pub input: Vec<i64>,
+    // Machine::instructions_executed().
+    pub executed: u64,
+}
+
+impl<M: Memory> Machine<M> {
+    // Snapshots hold memory as a dense Vec, so a machine with far writes into
+    // a PagedMemory makes a correspondingly large snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.to_vec(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            input: self.input.iter().copied().collect(),
+            executed: self.executed,
+        }
+    }
+
+    // Puts the machine back into the snapshotted state, reusing its existing
+    // memory allocation where the backend allows.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.keep_decoded(&snapshot.memory);
+        self.memory.load_image(&snapshot.memory);
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.input.clear();
+        self.input.extend(snapshot.input.iter());
+        self.executed = snapshot.executed;
+        if let Some(detector) = &mut self.loop_detector {
+            detector.rehash(&self.memory);
+            detector.reset();
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+}
+
+impl<M: Memory> Machine<M> {
+    // Before memory is replaced by `image`, drops the cached instructions
+    // that overlap a word `image` changes. Restoring a snapshot of the same
+    // program over and over keeps the cache warm.
+    fn keep_decoded(&mut self, image: &[i64]) {
+        if !self.use_decode_cache {
+            return;
+        }
+        let changed: Vec<usize> = (0..image.len())
+            .filter(|&addr| self.memory.read(addr) != image[addr])
+            .chain(
+                self.memory
+                    .nonzero()
+                    .map(|(addr, _)| addr)
+                    .filter(|&addr| addr >= image.len()),
+            )
+            .collect();
+        for addr in changed {
+            self.forget_decoded(addr);
+        }
+        self.decoded.resize(image.len(), None);
+    }
+}
+
+impl Machine {
+    pub fn from_snapshot(snapshot: &Snapshot) -> Machine {
+        let mut machine = Machine::new(vec![]);
+        machine.restore(snapshot);
+        machine
+    }
+}
+
+const HEADER: &str = "intcode-snapshot 1";
+
+// Snapshots are saved as text, one field per line:
+//
+//   intcode-snapshot 1
+//   ip 2
+//   rb 0
+//   executed 1
+//   input 5,6
+//   memory 3,9,8,9,10,9,4,9,99,-1,8
+impl Snapshot {
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "ip {}", self.ip)?;
+        writeln!(out, "rb {}", self.relative_base)?;
+        writeln!(out, "executed {}", self.executed)?;
+        writeln!(out, "input {}", join(&self.input))?;
+        writeln!(out, "memory {}", join(&self.memory))
+    }
+
+    pub fn read_from(input: &mut dyn Read) -> io::Result<Snapshot> {
+        let mut lines = BufReader::new(input).lines();
+        let mut next_line = |what: &str| {
+            lines
+                .next()
+                .unwrap_or_else(|| Err(invalid(format!("missing `{}` line", what))))
+        };
+        let header = next_line("intcode-snapshot")?;
+        if header != HEADER {
+            return Err(invalid(format!("not a snapshot: `{}`", header)));
+        }
+        let mut field = |name: &str| -> io::Result<String> {
+            let line = next_line(name)?;
+            match line.split_once(' ') {
+                Some((found, value)) if found == name => Ok(value.to_string()),
+                _ if line == name => Ok(String::new()),
+                _ => Err(invalid(format!("expected `{}`, got `{}`", name, line))),
+            }
+        };
+        Ok(Snapshot {
+            ip: parse(&field("ip")?)?,
+            relative_base: parse(&field("rb")?)?,
+            executed: parse(&field("executed")?)?,
+            input: parse_list(&field("input")?)?,
+            memory: parse_list(&field("memory")?)?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.write_to(&mut File::create(path)?)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        Snapshot::read_from(&mut File::open(path)?)
+    }
+}
+
+fn join(values: &[i64]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse<T: std::str::FromStr>(text: &str) -> io::Result<T> {
+    text.trim()
+        .parse()
+        .map_err(|_| invalid(format!("invalid number `{}`", text)))
+}
+
+fn parse_list(text: &str) -> io::Result<Vec<i64>> {
+    if text.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    text.split(',').map(parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, RunResult};
+
+    #[test]
+    fn restore_branches_from_mid_run() {
+        let mut machine = Machine::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert_eq!(machine.run().unwrap(), RunResult::NeedsInput);
+        let waiting = machine.snapshot();
+        for &(input, output) in &[(7, 0), (8, 1), (9, 0)] {
+            machine.restore(&waiting);
+            machine.push_input(input);
+            assert_eq!(machine.run().unwrap(), RunResult::Output(output));
+        }
+    }
+
+    #[test]
+    fn restore_keeps_unchanged_decodes() {
+        // Counts to 3 by patching its first instruction's parameter.
+        let program = assemble(
+            "start: out #1
+                    add [start+1], #1 -> [start+1]
+                    lt [start+1], #4 -> [flag]
+                    jt [flag], #start
+                    hlt
+             flag:  data 0",
+        )
+        .unwrap();
+        let mut machine = Machine::new(program);
+        let start = machine.snapshot();
+        for _ in 0..2 {
+            let mut output = vec![];
+            while let RunResult::Output(val) = machine.run().unwrap() {
+                output.push(val);
+            }
+            assert_eq!(output, vec![1, 2, 3]);
+            machine.restore(&start);
+            // The patched OUT has to be decoded again, but the rest hasn't
+            // changed.
+            assert!(machine.decoded[0].is_none());
+            assert!(machine.decoded[2].is_some());
+        }
+    }
+
+    #[test]
+    fn round_trip_through_text() {
+        let mut machine = Machine::new(vec![109, 3, 3, 11, 4, 11, 99]);
+        machine.push_input(5);
+        machine.push_input(6);
+        machine.step().unwrap();
+        let snapshot = machine.snapshot();
+
+        let mut text = vec![];
+        snapshot.write_to(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text.clone()).unwrap(),
+            "intcode-snapshot 1\nip 2\nrb 3\nexecuted 1\ninput 5,6\nmemory 109,3,3,11,4,11,99\n"
+        );
+        let loaded = Snapshot::read_from(&mut &text[..]).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut resumed = Machine::from_snapshot(&loaded);
+        assert_eq!(resumed.instructions_executed(), 1);
+        assert_eq!(resumed.run().unwrap(), RunResult::Output(5));
+        assert_eq!(resumed.pending_input().collect::<Vec<_>>(), vec![&6]);
+        assert_eq!(resumed.instructions_executed(), 3);
+    }
+
+    #[test]
+    fn empty_input_queue() {
+        let snapshot = Machine::new(vec![99]).snapshot();
+        let mut text = vec![];
+        snapshot.write_to(&mut text).unwrap();
+        assert_eq!(Snapshot::read_from(&mut &text[..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn bad_files() {
+        let error = |text: &str| {
+            Snapshot::read_from(&mut text.as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("1,2,3"), "not a snapshot: `1,2,3`");
+        assert_eq!(error("intcode-snapshot 1\nip 0\n"), "missing `rb` line");
+        assert_eq!(error("intcode-snapshot 1\nip x\n"), "invalid number `x`");
+        assert_eq!(
+            error("intcode-snapshot 1\nrb 0\n"),
+            "expected `ip`, got `rb 0`"
+        );
+        assert_eq!(
+            error("intcode-snapshot 1\nip 0\nrb 0\ninput\n"),
+            "expected `executed`, got `input`"
+        );
+    }
+}
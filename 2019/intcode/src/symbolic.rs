@@ -0,0 +1,616 @@
+use super::{Machine, Mode, NoTracer, Opcode, RunResult, TraceStep, Tracer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+// A constant plus a sum of variables times coefficients. Variables are the
+// indices Solver hands out.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LinearExpr {
+    pub constant: i64,
+    // (variable, coefficient) pairs in order of variable, without zero
+    // coefficients.
+    pub terms: Vec<(usize, i64)>,
+}
+
+impl LinearExpr {
+    pub fn constant(constant: i64) -> LinearExpr {
+        LinearExpr {
+            constant,
+            terms: vec![],
+        }
+    }
+
+    pub fn var(var: usize) -> LinearExpr {
+        LinearExpr {
+            constant: 0,
+            terms: vec![(var, 1)],
+        }
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn coefficient(&self, var: usize) -> i64 {
+        self.terms
+            .iter()
+            .find(|&&(v, _)| v == var)
+            .map_or(0, |&(_, coefficient)| coefficient)
+    }
+
+    // The value of the expression when each variable has the value at its
+    // index in `values`, or None if it overflows.
+    pub fn eval(&self, values: &[i64]) -> Option<i64> {
+        self.terms
+            .iter()
+            .try_fold(self.constant, |sum, &(var, coefficient)| {
+                sum.checked_add(coefficient.checked_mul(values[var])?)
+            })
+    }
+
+    // None if a coefficient or the constant overflows, like the Intcode
+    // arithmetic would.
+    fn add(&self, other: &LinearExpr) -> Option<LinearExpr> {
+        let mut terms = self.terms.clone();
+        for &(var, coefficient) in &other.terms {
+            match terms.binary_search_by_key(&var, |&(v, _)| v) {
+                Ok(i) => terms[i].1 = terms[i].1.checked_add(coefficient)?,
+                Err(i) => terms.insert(i, (var, coefficient)),
+            }
+        }
+        terms.retain(|&(_, coefficient)| coefficient != 0);
+        Some(LinearExpr {
+            constant: self.constant.checked_add(other.constant)?,
+            terms,
+        })
+    }
+
+    fn scale(&self, factor: i64) -> Option<LinearExpr> {
+        if factor == 0 {
+            return Some(LinearExpr::constant(0));
+        }
+        let terms = self
+            .terms
+            .iter()
+            .map(|&(var, coefficient)| Some((var, coefficient.checked_mul(factor)?)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(LinearExpr {
+            constant: self.constant.checked_mul(factor)?,
+            terms,
+        })
+    }
+}
+
+// Writes expressions like `250000*v0 + v1 - 3`.
+impl fmt::Display for LinearExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(var, coefficient)) in self.terms.iter().enumerate() {
+            let magnitude = if i == 0 {
+                if coefficient < 0 {
+                    write!(f, "-")?;
+                }
+                coefficient.abs()
+            } else {
+                write!(f, " {} ", if coefficient < 0 { '-' } else { '+' })?;
+                coefficient.abs()
+            };
+            if magnitude != 1 {
+                write!(f, "{}*", magnitude)?;
+            }
+            write!(f, "v{}", var)?;
+        }
+        match (self.terms.is_empty(), self.constant) {
+            (true, constant) => write!(f, "{}", constant),
+            (false, 0) => Ok(()),
+            (false, constant) if constant < 0 => write!(f, " - {}", -(constant as i128)),
+            (false, constant) => write!(f, " + {}", constant),
+        }
+    }
+}
+
+// What a solution has to produce.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Target {
+    // The value at an address once the program halts.
+    Memory(usize),
+    // The nth output (counting from 0) of a run that halts.
+    Output(usize),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SolveMethod {
+    // The target was this expression of the variables, on a path that
+    // didn't depend on them, so the solutions came from solving it.
+    Linear(LinearExpr),
+    // The solver ran the program for every assignment of the variables.
+    Enumeration,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Solutions {
+    // The value of each variable, by its index, for every solution, in
+    // lexicographic order.
+    pub assignments: Vec<Vec<i64>>,
+    pub method: SolveMethod,
+}
+
+#[derive(Clone, Copy)]
+enum InputValue {
+    Value(i64),
+    Var(usize),
+}
+
+// Finds values for some of a program's memory cells or inputs that make it
+// produce a target value.
+//
+// The solver first runs the program once concolically: concretely, with each
+// variable at the start of its range, while tracking which cells hold linear
+// expressions of the variables. If the target comes out as a linear
+// expression and nothing the program branched on, jumped to, or used as an
+// address depended on a variable, the expression holds for every
+// assignment, and solving it gives the candidates. Otherwise, for example
+// when two variables are multiplied or compared, it falls back to running
+// every assignment. Either way, each solution is checked with a real run.
+pub struct Solver {
+    machine: Machine,
+    ranges: Vec<RangeInclusive<i64>>,
+    // (address, variable) pairs.
+    memory_vars: Vec<(usize, usize)>,
+    inputs: Vec<InputValue>,
+}
+
+impl Solver {
+    // Runs copies of `machine`, so it can be set up with a dialect, limits,
+    // and so on first. A budget keeps enumeration from getting stuck in an
+    // infinite loop.
+    pub fn new(machine: Machine) -> Solver {
+        Solver {
+            machine,
+            ranges: vec![],
+            memory_vars: vec![],
+            inputs: vec![],
+        }
+    }
+
+    // Makes the value at `addr` a variable, and returns its index.
+    pub fn memory_var(&mut self, addr: usize, range: RangeInclusive<i64>) -> usize {
+        let var = self.new_var(range);
+        self.memory_vars.push((addr, var));
+        var
+    }
+
+    // Adds a concrete value to the program's input.
+    pub fn push_input(&mut self, val: i64) {
+        self.inputs.push(InputValue::Value(val));
+    }
+
+    // Adds a variable to the program's input, and returns its index.
+    pub fn input_var(&mut self, range: RangeInclusive<i64>) -> usize {
+        let var = self.new_var(range);
+        self.inputs.push(InputValue::Var(var));
+        var
+    }
+
+    fn new_var(&mut self, range: RangeInclusive<i64>) -> usize {
+        assert!(!range.is_empty(), "empty range for v{}", self.ranges.len());
+        self.ranges.push(range);
+        self.ranges.len() - 1
+    }
+
+    // Every assignment of the variables that makes `target` equal `value`.
+    pub fn solve(&self, target: Target, value: i64) -> Solutions {
+        let seed: Vec<i64> = self.ranges.iter().map(|r| *r.start()).collect();
+        let mut shadow = Shadow::new(self);
+        let expr = match self.run(&seed, &mut shadow) {
+            Some(run) if !shadow.path_dependent => shadow.target(target, &run),
+            _ => None,
+        };
+        let mut assignments = vec![];
+        let method = match expr {
+            Some(expr) => {
+                assignments = solve_linear(&expr, value, &self.ranges)
+                    .into_iter()
+                    .filter(|values| self.check(values, target, value))
+                    .collect();
+                SolveMethod::Linear(expr)
+            }
+            None => {
+                for_each_assignment(&self.ranges, |values| {
+                    if self.check(values, target, value) {
+                        assignments.push(values.to_vec());
+                    }
+                });
+                SolveMethod::Enumeration
+            }
+        };
+        Solutions {
+            assignments,
+            method,
+        }
+    }
+
+    // Whether the program produces `value` for `target` with `values`.
+    pub fn check(&self, values: &[i64], target: Target, value: i64) -> bool {
+        self.run(values, &mut NoTracer)
+            .and_then(|run| run.target(target))
+            == Some(value)
+    }
+
+    // Runs the program with `values` until it halts, or returns None if it
+    // fails any other way.
+    fn run<T: Tracer>(&self, values: &[i64], tracer: &mut T) -> Option<Run> {
+        let mut machine = self.machine.clone();
+        for &(addr, var) in &self.memory_vars {
+            machine.write(addr, values[var]).ok()?;
+        }
+        for &input in &self.inputs {
+            machine.push_input(match input {
+                InputValue::Value(val) => val,
+                InputValue::Var(var) => values[var],
+            });
+        }
+        let mut outputs = vec![];
+        loop {
+            match machine.run_traced(tracer).ok()? {
+                RunResult::Output(val) => outputs.push(val),
+                RunResult::Halted => {
+                    return Some(Run {
+                        memory: machine.into_memory(),
+                        outputs,
+                    })
+                }
+                RunResult::NeedsInput | RunResult::BudgetExhausted => return None,
+            }
+        }
+    }
+}
+
+struct Run {
+    memory: Vec<i64>,
+    outputs: Vec<i64>,
+}
+impl Run {
+    fn target(&self, target: Target) -> Option<i64> {
+        match target {
+            Target::Memory(addr) => Some(self.memory.get(addr).copied().unwrap_or(0)),
+            Target::Output(n) => self.outputs.get(n).copied(),
+        }
+    }
+}
+
+// What a shadowed cell holds.
+#[derive(Clone)]
+enum Value {
+    Linear(LinearExpr),
+    // Depends on the variables some other way.
+    Opaque,
+}
+impl Value {
+    fn as_constant(&self) -> Option<i64> {
+        match self {
+            Value::Linear(expr) if expr.is_constant() => Some(expr.constant),
+            _ => None,
+        }
+    }
+}
+
+// The symbolic half of a concolic run: the expression in every memory cell
+// that depends on a variable. Cells that don't are just their concrete values.
+struct Shadow {
+    cells: HashMap<usize, Value>,
+    inputs: Vec<InputValue>,
+    next_input: usize,
+    outputs: Vec<Value>,
+    // Set once control flow, an address, or an instruction depended on a
+    // variable, after which the expressions only describe this run.
+    path_dependent: bool,
+}
+
+impl Shadow {
+    fn new(solver: &Solver) -> Shadow {
+        Shadow {
+            cells: solver
+                .memory_vars
+                .iter()
+                .map(|&(addr, var)| (addr, Value::Linear(LinearExpr::var(var))))
+                .collect(),
+            inputs: solver.inputs.clone(),
+            next_input: 0,
+            outputs: vec![],
+            path_dependent: false,
+        }
+    }
+
+    // The target as an expression, if it's linear.
+    fn target(&self, target: Target, run: &Run) -> Option<LinearExpr> {
+        let value = match target {
+            Target::Memory(addr) => self.cells.get(&addr),
+            Target::Output(n) => Some(self.outputs.get(n)?),
+        };
+        match value {
+            Some(Value::Linear(expr)) => Some(expr.clone()),
+            Some(Value::Opaque) => None,
+            None => run.target(target).map(LinearExpr::constant),
+        }
+    }
+
+    // What parameter `i` of `step` read.
+    fn operand(&self, step: &TraceStep, i: usize) -> Value {
+        let param_cell = self.cells.get(&(step.ip + 1 + i));
+        let concrete = || Value::Linear(LinearExpr::constant(step.operands[i]));
+        let addr = match step.modes[i] {
+            Mode::Immediate => return param_cell.cloned().unwrap_or_else(concrete),
+            // Reading from an address that depends on a variable.
+            _ if param_cell.is_some() => return Value::Opaque,
+            Mode::Position => step.params[i],
+            Mode::Relative => step.params[i] + step.relative_base,
+        };
+        // The machine already checked the address.
+        let addr = usize::try_from(addr).unwrap();
+        self.cells.get(&addr).cloned().unwrap_or_else(concrete)
+    }
+
+    fn store(&mut self, step: &TraceStep, param: usize, value: Value) {
+        if self.cells.contains_key(&(step.ip + 1 + param)) {
+            // The address depends on a variable.
+            self.path_dependent = true;
+            return;
+        }
+        let addr = step.operands[param] as usize;
+        if value.as_constant().is_some() {
+            self.cells.remove(&addr);
+        } else {
+            self.cells.insert(addr, value);
+        }
+    }
+}
+
+// The concrete value `step` wrote.
+fn written(step: &TraceStep) -> Value {
+    Value::Linear(LinearExpr::constant(step.write.map_or(0, |(_, val)| val)))
+}
+
+impl Tracer for Shadow {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.path_dependent {
+            return;
+        }
+        if self.cells.contains_key(&step.ip) {
+            // The instruction itself depends on a variable.
+            self.path_dependent = true;
+            return;
+        }
+        let linear = |value: Value, f: &dyn Fn(LinearExpr) -> Option<LinearExpr>| match value {
+            Value::Linear(expr) => f(expr).map_or(Value::Opaque, Value::Linear),
+            Value::Opaque => Value::Opaque,
+        };
+        let constant = |value: &Value| value.as_constant().is_some();
+        match step.opcode {
+            Opcode::Add => {
+                let b = self.operand(step, 1);
+                let sum = match b {
+                    Value::Linear(b) => linear(self.operand(step, 0), &|a| a.add(&b)),
+                    Value::Opaque => Value::Opaque,
+                };
+                self.store(step, 2, sum);
+            }
+            Opcode::Mul => {
+                let (a, b) = (self.operand(step, 0), self.operand(step, 1));
+                let product = match (a.as_constant(), b.as_constant()) {
+                    (Some(a), _) => linear(b, &|b| b.scale(a)),
+                    (_, Some(b)) => linear(a, &|a| a.scale(b)),
+                    _ => Value::Opaque,
+                };
+                self.store(step, 2, product);
+            }
+            Opcode::LessThan | Opcode::Equals => {
+                let result = if constant(&self.operand(step, 0)) && constant(&self.operand(step, 1))
+                {
+                    written(step)
+                } else {
+                    Value::Opaque
+                };
+                self.store(step, 2, result);
+            }
+            Opcode::In => {
+                let value = match self.inputs.get(self.next_input) {
+                    Some(&InputValue::Var(var)) => Value::Linear(LinearExpr::var(var)),
+                    _ => written(step),
+                };
+                self.next_input += 1;
+                self.store(step, 0, value);
+            }
+            Opcode::Out => {
+                let value = self.operand(step, 0);
+                self.outputs.push(value);
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                // A jump that isn't taken doesn't read its target.
+                let taken = step.operands.len() > 1;
+                if !constant(&self.operand(step, 0)) || (taken && !constant(&self.operand(step, 1)))
+                {
+                    self.path_dependent = true;
+                }
+            }
+            Opcode::AdjustRelativeBase => {
+                if !constant(&self.operand(step, 0)) {
+                    self.path_dependent = true;
+                }
+            }
+            Opcode::Halt => {}
+            Opcode::Custom(def) => {
+                let shadowed = (0..def.params).any(|i| {
+                    self.cells.contains_key(&(step.ip + 1 + i))
+                        || (Some(i) != def.write_param && !constant(&self.operand(step, i)))
+                });
+                if shadowed {
+                    self.path_dependent = true;
+                } else if let Some(w) = def.write_param {
+                    self.cells.remove(&(step.operands[w] as usize));
+                }
+            }
+        }
+    }
+}
+
+// Calls `f` with every assignment of values from `ranges`, in lexicographic
+// order.
+fn for_each_assignment(ranges: &[RangeInclusive<i64>], mut f: impl FnMut(&[i64])) {
+    let mut values: Vec<i64> = ranges.iter().map(|r| *r.start()).collect();
+    loop {
+        f(&values);
+        // Advance the last variable that isn't at the end of its range, and
+        // reset the ones after it.
+        let mut i = values.len();
+        loop {
+            if i == 0 {
+                return;
+            }
+            i -= 1;
+            if values[i] < *ranges[i].end() {
+                values[i] += 1;
+                break;
+            }
+            values[i] = *ranges[i].start();
+        }
+    }
+}
+
+// The assignments within `ranges` for which `expr` equals `value`. The last
+// variable in `expr` is solved for, and the others are enumerated.
+fn solve_linear(expr: &LinearExpr, value: i64, ranges: &[RangeInclusive<i64>]) -> Vec<Vec<i64>> {
+    let mut solutions = vec![];
+    let (pivot, coefficient) = match expr.terms.last() {
+        Some(&term) => term,
+        None => {
+            if expr.constant == value {
+                for_each_assignment(ranges, |values| solutions.push(values.to_vec()));
+            }
+            return solutions;
+        }
+    };
+    let mut others = ranges.to_vec();
+    others[pivot] = *ranges[pivot].start()..=*ranges[pivot].start();
+    let rest = &expr.terms[..expr.terms.len() - 1];
+    for_each_assignment(&others, |values| {
+        // Work in i128 so the search can't overflow.
+        let remainder = rest.iter().fold(
+            i128::from(value) - i128::from(expr.constant),
+            |sum, &(var, c)| sum - i128::from(c) * i128::from(values[var]),
+        );
+        let coefficient = i128::from(coefficient);
+        if remainder % coefficient != 0 {
+            return;
+        }
+        if let Ok(solution) = i64::try_from(remainder / coefficient) {
+            if ranges[pivot].contains(&solution) {
+                let mut values = values.to_vec();
+                values[pivot] = solution;
+                solutions.push(values);
+            }
+        }
+    });
+    solutions.sort();
+    solutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    // Every assignment that works, found by brute force.
+    fn brute_force(solver: &Solver, target: Target, value: i64) -> Vec<Vec<i64>> {
+        let mut all = vec![];
+        for_each_assignment(&solver.ranges, |values| {
+            if solver.check(values, target, value) {
+                all.push(values.to_vec());
+            }
+        });
+        all
+    }
+
+    #[test]
+    fn linear_memory() {
+        // Like day 2: the first instruction reads through the variables as
+        // addresses, but then overwrites its result with their sum.
+        let program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 17, 3, 1, 3, 18, 0, 99, 7, -4];
+        let mut solver = Solver::new(Machine::new(program));
+        let x = solver.memory_var(1, 0..=18);
+        let y = solver.memory_var(2, 0..=18);
+        let solutions = solver.solve(Target::Memory(0), 66);
+        let expr = match &solutions.method {
+            SolveMethod::Linear(expr) => expr,
+            method => panic!("{:?}", method),
+        };
+        assert_eq!(expr.to_string(), "7*v0 + 7*v1 - 4");
+        assert_eq!((expr.coefficient(x), expr.coefficient(y)), (7, 7));
+        assert_eq!(
+            solutions.assignments,
+            brute_force(&solver, Target::Memory(0), 66)
+        );
+        assert_eq!(solutions.assignments.len(), 11);
+    }
+
+    #[test]
+    fn linear_input() {
+        let program = assemble(
+            "      in -> [x]
+                   in -> [y]
+                   mul [x], #-3 -> [x]
+                   add [x], [y] -> [x]
+                   out [x]
+                   hlt
+            x:     data 0
+            y:     data 0",
+        )
+        .unwrap();
+        let mut solver = Solver::new(Machine::new(program));
+        solver.input_var(-10..=10);
+        solver.push_input(5);
+        let solutions = solver.solve(Target::Output(0), 20);
+        assert_eq!(
+            solutions.method,
+            SolveMethod::Linear(LinearExpr {
+                constant: 5,
+                terms: vec![(0, -3)]
+            })
+        );
+        assert_eq!(solutions.assignments, vec![vec![-5]]);
+        // Not a multiple of 3.
+        assert_eq!(
+            solver.solve(Target::Output(0), 21).assignments,
+            Vec::<Vec<i64>>::new()
+        );
+    }
+
+    #[test]
+    fn nonlinear_falls_back() {
+        // Outputs x*y if x < y, and 0 otherwise.
+        let program = assemble(
+            "      in -> [x]
+                   in -> [y]
+                   lt [x], [y] -> [t]
+                   jf [t], #skip
+                   mul [x], [y] -> [r]
+            skip:  out [r]
+                   hlt
+            x:     data 0
+            y:     data 0
+            t:     data 0
+            r:     data 0",
+        )
+        .unwrap();
+        let mut solver = Solver::new(Machine::new(program));
+        solver.input_var(1..=12);
+        solver.input_var(1..=12);
+        let solutions = solver.solve(Target::Output(0), 12);
+        assert_eq!(solutions.method, SolveMethod::Enumeration);
+        assert_eq!(
+            solutions.assignments,
+            vec![vec![1, 12], vec![2, 6], vec![3, 4]]
+        );
+    }
+}
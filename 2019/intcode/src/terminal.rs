@@ -0,0 +1,193 @@
+use super::{is_ascii_output, Input, Output};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, Write};
+
+// A Device that connects an ASCII program to a terminal. Output text is written
+// as it's produced, and non-ASCII outputs are shown on their own line as
+// `[result N]`. Whenever the program wants input, it gets the next scripted
+// line if any are left, and otherwise the next line from `input`. Every line
+// sent to the program is recorded in a transcript, so the session can be
+// replayed later.
+pub struct Terminal<R: BufRead, W: Write> {
+    input: R,
+    out: W,
+    script: VecDeque<String>,
+    // The rest of the line the program is currently reading.
+    line: VecDeque<i64>,
+    transcript: Vec<String>,
+    at_line_start: bool,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    pub fn new(input: R, out: W) -> Terminal<R, W> {
+        Terminal {
+            input,
+            out,
+            script: VecDeque::new(),
+            line: VecDeque::new(),
+            transcript: vec![],
+            at_line_start: true,
+            error: None,
+        }
+    }
+
+    // Queues lines to send before reading from the terminal. Each one is
+    // echoed when it's sent, as if it had been typed.
+    pub fn replay<S: Into<String>>(&mut self, lines: impl IntoIterator<Item = S>) {
+        self.script.extend(lines.into_iter().map(Into::into));
+    }
+
+    // Every line sent to the program so far, scripted or typed.
+    pub fn transcript(&self) -> &[String] {
+        &self.transcript
+    }
+
+    pub fn write_transcript(&self, out: &mut dyn Write) -> io::Result<()> {
+        for line in &self.transcript {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // Returns the output writer, or the first error reading or writing the
+    // terminal.
+    pub fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.out),
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        if let Some(line) = self.script.pop_front() {
+            writeln!(self.out, "{}", line)?;
+            self.at_line_start = true;
+            return Ok(Some(line));
+        }
+        self.out.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+        line.truncate(len);
+        self.at_line_start = true;
+        Ok(Some(line))
+    }
+
+    fn write_output(&mut self, val: i64) -> io::Result<()> {
+        if is_ascii_output(val) {
+            let c = val as u8 as char;
+            write!(self.out, "{}", c)?;
+            self.at_line_start = c == '\n';
+            if self.at_line_start {
+                self.out.flush()?;
+            }
+        } else {
+            if !self.at_line_start {
+                writeln!(self.out)?;
+            }
+            writeln!(self.out, "[result {}]", val)?;
+            self.at_line_start = true;
+        }
+        Ok(())
+    }
+
+    fn record_error(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Input for Terminal<R, W> {
+    fn input(&mut self) -> Option<i64> {
+        if self.line.is_empty() {
+            if self.error.is_some() {
+                return None;
+            }
+            let line = match self.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                }
+            };
+            self.line.extend(line.chars().map(|c| c as i64));
+            self.line.push_back('\n' as i64);
+            self.transcript.push(line);
+        }
+        self.line.pop_front()
+    }
+}
+impl<R: BufRead, W: Write> Output for Terminal<R, W> {
+    fn output(&mut self, val: i64) {
+        let result = self.write_output(val);
+        self.record_error(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, run_program, ErrorCause};
+
+    // Asks for a line, echoes it, and then outputs a non-ASCII result.
+    fn echo() -> Vec<i64> {
+        assemble(
+            "      out #63
+                   out #10
+            loop:  in -> [c]
+                   out [c]
+                   eq [c], #10 -> [t]
+                   jf [t], #loop
+                   out #1234
+                   hlt
+            c:     data 0
+            t:     data 0",
+        )
+        .unwrap()
+    }
+
+    fn session(typed: &str, script: &[&str]) -> (String, Vec<String>) {
+        let mut terminal = Terminal::new(typed.as_bytes(), vec![]);
+        terminal.replay(script.iter().copied());
+        run_program(echo(), &mut terminal).unwrap();
+        let transcript = terminal.transcript().to_vec();
+        let out = terminal.finish().unwrap();
+        (String::from_utf8(out).unwrap(), transcript)
+    }
+
+    #[test]
+    fn typed_input() {
+        assert_eq!(
+            session("hi there\r\nignored\n", &[]),
+            (
+                "?\nhi there\n[result 1234]\n".to_string(),
+                vec!["hi there".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn replayed_input_is_echoed() {
+        assert_eq!(
+            session("", &["hi"]),
+            (
+                "?\nhi\nhi\n[result 1234]\n".to_string(),
+                vec!["hi".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn end_of_input() {
+        let mut terminal = Terminal::new(&b""[..], vec![]);
+        let err = run_program(echo(), &mut terminal).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::MissingInput);
+        assert_eq!(terminal.finish().unwrap(), b"?\n");
+    }
+}
@@ -0,0 +1,154 @@
+use super::disasm::operand;
+use super::{Mode, Opcode};
+use std::io;
+
+// One executed instruction, as seen by a Tracer.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TraceStep<'a> {
+    pub ip: usize,
+    pub opcode: Opcode,
+    pub modes: &'a [Mode],
+    // The raw parameters following the instruction.
+    pub params: &'a [i64],
+    // For each parameter, the value it read, or for the parameter the
+    // instruction writes through, the address it wrote to. A jump that isn't
+    // taken never reads its target, so only its condition is here.
+    pub operands: &'a [i64],
+    // The relative base before the instruction ran.
+    pub relative_base: i64,
+    // The address and new value of any memory the instruction wrote.
+    pub write: Option<(usize, i64)>,
+}
+
+pub trait Tracer {
+    fn trace(&mut self, step: &TraceStep);
+    // Machines skip collecting TraceSteps when this returns false.
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+// The tracer used when nobody's watching. Since Machine::run_traced() is
+// generic over the tracer, the tracing code compiles away.
+pub struct NoTracer;
+impl Tracer for NoTracer {
+    fn trace(&mut self, _: &TraceStep) {}
+    fn enabled(&self) -> bool {
+        false
+    }
+}
+
+impl<F: FnMut(&TraceStep)> Tracer for F {
+    fn trace(&mut self, step: &TraceStep) {
+        self(step)
+    }
+}
+
+// Writes one line per instruction, like:
+//
+//   0004: ADD [rb+3]@103=7, #5 -> [100]=12
+//
+// Reads show the value they read, and the write shows the value stored.
+pub struct WriteTracer<W: io::Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+impl<W: io::Write> WriteTracer<W> {
+    pub fn new(out: W) -> WriteTracer<W> {
+        WriteTracer { out, error: None }
+    }
+    // Returns the writer, or the first error writing to it.
+    pub fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.out),
+        }
+    }
+}
+impl<W: io::Write> Tracer for WriteTracer<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = writeln!(self.out, "{}", format_step(step)) {
+            self.error = Some(err);
+        }
+    }
+}
+
+fn format_step(step: &TraceStep) -> String {
+    let write_param = step.opcode.write_param();
+    let describe = |i: usize| {
+        let mut text = operand(step.modes[i], step.params[i]);
+        if let Mode::Relative = step.modes[i] {
+            let addr = step.params[i] + step.relative_base;
+            text.push_str(&format!("@{}", addr));
+        }
+        text
+    };
+    let mut line = format!("{:04}: {}", step.ip, step.opcode.mnemonic());
+    let reads: Vec<String> = (0..step.params.len())
+        .filter(|&i| Some(i) != write_param)
+        .map(|i| match (step.modes[i], step.operands.get(i)) {
+            (Mode::Position, Some(val)) | (Mode::Relative, Some(val)) => {
+                format!("{}={}", describe(i), val)
+            }
+            _ => describe(i),
+        })
+        .collect();
+    if !reads.is_empty() {
+        line.push(' ');
+        line.push_str(&reads.join(", "));
+    }
+    if let (Some(w), Some((_, value))) = (write_param, step.write) {
+        line.push_str(&format!(" -> {}={}", describe(w), value));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_intcode_traced, VecState};
+
+    #[test]
+    fn closure_sees_every_step() {
+        let mut steps = vec![];
+        run_intcode_traced(
+            vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+            &mut VecState::new(vec![8]),
+            &mut |step: &TraceStep| {
+                steps.push((step.ip, step.opcode, step.operands.to_vec(), step.write))
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                (0, Opcode::In, vec![9], Some((9, 8))),
+                (2, Opcode::Equals, vec![8, 8, 9], Some((9, 1))),
+                (6, Opcode::Out, vec![1], None),
+                (8, Opcode::Halt, vec![], None),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_tracer() {
+        let mut tracer = WriteTracer::new(vec![]);
+        run_intcode_traced(
+            vec![109, 10, 22201, 0, 1, 0, 1105, 1, 9, 99, 5, 6],
+            &mut VecState::new(vec![]),
+            &mut tracer,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(tracer.finish().unwrap()).unwrap(),
+            "0000: ARB #10
+0002: ADD [rb+0]@10=5, [rb+1]@11=6 -> [rb+0]@10=11
+0006: JT #1, #9
+0009: HLT
+"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+// Compiles Intcode programs into Rust modules in OUT_DIR, which src/lib.rs
+// includes.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Small programs from the puzzle descriptions.
+const EXAMPLES: &[(&str, &str)] = &[
+    // Day 2: writes over its own code.
+    ("day2_example", "1,9,10,3,2,3,11,0,99,30,40,50"),
+    // Day 5: compares its input to 8, with jumps.
+    (
+        "day5_example",
+        "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,\
+         1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,\
+         1105,1,46,98,99",
+    ),
+    // Day 9: outputs a copy of itself.
+    (
+        "quine",
+        "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99",
+    ),
+];
+
+// Programs from the intcode crate's own tests, which the compiled code has to
+// run the same way the interpreter does.
+const TESTS: &[(&str, &[i64])] = &[
+    ("day2_add", &[1, 0, 0, 0, 99]),
+    ("day2_mul", &[2, 3, 0, 3, 99]),
+    ("day2_square", &[2, 4, 4, 5, 99, 0]),
+    ("day2_patch", &[1, 1, 1, 4, 99, 5, 6, 0, 99]),
+    ("day5_position_eq8", &[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]),
+    ("day5_position_lt8", &[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8]),
+    ("day5_imm_eq8", &[3, 3, 1108, -1, 8, 3, 4, 3, 99]),
+    ("day5_imm_lt8", &[3, 3, 1107, -1, 8, 3, 4, 3, 99]),
+    (
+        "day5_jump_pos",
+        &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+    ),
+    (
+        "day5_jump_imm",
+        &[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+    ),
+    ("day9_multiply", &[1102, 34915192, 34915192, 7, 4, 7, 99, 0]),
+    ("day9_large", &[104, 1125899906842624, 99]),
+    ("unknown_opcode", &[1, 0, 0, 0, 42]),
+    ("unknown_mode", &[301, 0, 0, 0, 99]),
+    ("write_to_immediate", &[11101, 0, 0, 0, 99]),
+    ("negative_address", &[1, -1, 0, 0, 99]),
+    ("negative_jump", &[1105, 1, -7]),
+    ("ip_out_of_range", &[1101, 0, 0, 0]),
+    ("missing_input", &[104, 5, 3, 0, 99]),
+    ("far_write", &[1101, 1, 1, 1 << 30, 99]),
+    ("overflow_add", &[104, 0, 1101, i64::MAX, 1, 0, 99]),
+    ("overflow_mul", &[1102, i64::MIN, -1, 0, 99]),
+    ("overflow_arb", &[109, i64::MAX, 109, 1, 99]),
+    (
+        "overflow_relative_write",
+        &[109, i64::MAX, 21101, 0, 0, 1, 99],
+    ),
+    ("overflow_relative_read", &[109, i64::MIN, 204, -1, 99]),
+    // Jumps that aren't taken, to addresses that don't exist.
+    ("untaken_jt", &[5, 7, -1, 104, 1, 99, 0, 0]),
+    ("untaken_jf", &[6, 8, 9, 104, 1, 99, 0, 0, 1, -1]),
+    ("untaken_relative", &[2105, 0, -10, 99]),
+    ("input_to_negative_address", &[3, -1, 99]),
+];
+
+// Self-modifying programs, in assembly.
+const ASSEMBLY: &[(&str, &str)] = &[
+    // Patches a compiled instruction on every pass through its loop.
+    (
+        "patches_loop",
+        "start: out #1
+                add [start+1], #1 -> [start+1]
+                lt [start+1], #4 -> [flag]
+                jt [flag], #start
+                hlt
+         flag:  data 0",
+    ),
+    // The first instruction's write sends the run to the interpreter, which
+    // then patches a later block before jumping to it.
+    (
+        "interpreter_patches_block",
+        "add #1, #0 -> [3]
+         add #5, #0 -> [b+1]
+         jt #1, #b
+      b: out #7
+         hlt",
+    ),
+];
+
+// Puzzle inputs, relative to this crate. Day 5's program patches its own code
+// before its first jump, so analyze() finds little of it and it mostly runs in
+// the interpreter.
+const INPUTS: &[(&str, &str)] = &[("day5", "../5/input"), ("day9", "../9/input")];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let write = |name: &str, program: &[i64]| {
+        let path = Path::new(&out_dir).join(format!("{}.rs", name));
+        fs::write(path, intcode::compile_to_rust(program)).unwrap();
+    };
+    for (name, text) in EXAMPLES {
+        write(name, &intcode::parse_program(text).unwrap());
+    }
+    for (name, program) in TESTS {
+        write(name, program);
+    }
+    for (name, text) in ASSEMBLY {
+        write(name, &intcode::assemble(text).unwrap());
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    for (name, path) in INPUTS {
+        println!("cargo:rerun-if-changed={}", path);
+        write(name, &intcode::load_program(path).unwrap());
+    }
+}
@@ -0,0 +1,167 @@
+// Intcode programs compiled to Rust by build.rs. Each module has the same
+// interface; see intcode::compile_to_rust().
+
+macro_rules! compiled {
+    ($name:ident) => {
+        #[allow(clippy::all)]
+        pub mod $name {
+            include!(concat!(env!("OUT_DIR"), "/", stringify!($name), ".rs"));
+        }
+    };
+}
+
+compiled!(day2_example);
+compiled!(day5_example);
+compiled!(quine);
+compiled!(day2_add);
+compiled!(day2_mul);
+compiled!(day2_square);
+compiled!(day2_patch);
+compiled!(day5_position_eq8);
+compiled!(day5_position_lt8);
+compiled!(day5_imm_eq8);
+compiled!(day5_imm_lt8);
+compiled!(day5_jump_pos);
+compiled!(day5_jump_imm);
+compiled!(day9_multiply);
+compiled!(day9_large);
+compiled!(unknown_opcode);
+compiled!(unknown_mode);
+compiled!(write_to_immediate);
+compiled!(negative_address);
+compiled!(negative_jump);
+compiled!(ip_out_of_range);
+compiled!(missing_input);
+compiled!(far_write);
+compiled!(overflow_add);
+compiled!(overflow_mul);
+compiled!(overflow_arb);
+compiled!(overflow_relative_write);
+compiled!(overflow_relative_read);
+compiled!(untaken_jt);
+compiled!(untaken_jf);
+compiled!(untaken_relative);
+compiled!(input_to_negative_address);
+compiled!(patches_loop);
+compiled!(interpreter_patches_block);
+compiled!(day5);
+compiled!(day9);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use intcode::{run_intcode_input, IntcodeError, IntcodeResult};
+
+    type Run = fn(&[i64]) -> Result<IntcodeResult, IntcodeError>;
+
+    // The compiled program has to match the interpreter exactly, including
+    // its final memory and any error.
+    fn check(program: &[i64], compiled: Run, input: &[i64]) {
+        assert_eq!(
+            compiled(input),
+            run_intcode_input(program.to_vec(), input),
+            "input {:?}",
+            input
+        );
+    }
+
+    #[test]
+    fn self_modifying() {
+        check(day2_example::PROGRAM, day2_example::run_input, &[]);
+        let result = day2_example::run_input(&[]).unwrap();
+        assert_eq!(result.memory[0], 3500);
+    }
+
+    #[test]
+    fn examples() {
+        for input in 0..12 {
+            check(day5_example::PROGRAM, day5_example::run_input, &[input]);
+        }
+        // Not enough input.
+        check(day5_example::PROGRAM, day5_example::run_input, &[]);
+        check(quine::PROGRAM, quine::run_input, &[]);
+        assert_eq!(quine::run_input(&[]).unwrap().output, quine::PROGRAM);
+    }
+
+    #[test]
+    fn intcode_tests() {
+        let no_input: &[(&[i64], Run)] = &[
+            (day2_add::PROGRAM, day2_add::run_input),
+            (day2_mul::PROGRAM, day2_mul::run_input),
+            (day2_square::PROGRAM, day2_square::run_input),
+            (day2_patch::PROGRAM, day2_patch::run_input),
+            (day9_multiply::PROGRAM, day9_multiply::run_input),
+            (day9_large::PROGRAM, day9_large::run_input),
+            (unknown_opcode::PROGRAM, unknown_opcode::run_input),
+            (unknown_mode::PROGRAM, unknown_mode::run_input),
+            (write_to_immediate::PROGRAM, write_to_immediate::run_input),
+            (negative_address::PROGRAM, negative_address::run_input),
+            (negative_jump::PROGRAM, negative_jump::run_input),
+            (ip_out_of_range::PROGRAM, ip_out_of_range::run_input),
+            (missing_input::PROGRAM, missing_input::run_input),
+            (far_write::PROGRAM, far_write::run_input),
+            (overflow_add::PROGRAM, overflow_add::run_input),
+            (overflow_mul::PROGRAM, overflow_mul::run_input),
+            (overflow_arb::PROGRAM, overflow_arb::run_input),
+            (
+                overflow_relative_write::PROGRAM,
+                overflow_relative_write::run_input,
+            ),
+            (
+                overflow_relative_read::PROGRAM,
+                overflow_relative_read::run_input,
+            ),
+            (untaken_jt::PROGRAM, untaken_jt::run_input),
+            (untaken_jf::PROGRAM, untaken_jf::run_input),
+            (untaken_relative::PROGRAM, untaken_relative::run_input),
+            (
+                input_to_negative_address::PROGRAM,
+                input_to_negative_address::run_input,
+            ),
+            (patches_loop::PROGRAM, patches_loop::run_input),
+            (
+                interpreter_patches_block::PROGRAM,
+                interpreter_patches_block::run_input,
+            ),
+        ];
+        for &(program, run) in no_input {
+            check(program, run, &[]);
+        }
+        // The input has to stay unread when the address is bad.
+        check(
+            input_to_negative_address::PROGRAM,
+            input_to_negative_address::run_input,
+            &[5],
+        );
+        let with_input: &[(&[i64], Run)] = &[
+            (day5_position_eq8::PROGRAM, day5_position_eq8::run_input),
+            (day5_position_lt8::PROGRAM, day5_position_lt8::run_input),
+            (day5_imm_eq8::PROGRAM, day5_imm_eq8::run_input),
+            (day5_imm_lt8::PROGRAM, day5_imm_lt8::run_input),
+            (day5_jump_pos::PROGRAM, day5_jump_pos::run_input),
+            (day5_jump_imm::PROGRAM, day5_jump_imm::run_input),
+        ];
+        for &(program, run) in with_input {
+            for input in -1..10 {
+                check(program, run, &[input]);
+            }
+        }
+    }
+
+    #[test]
+    fn interpreter_writes_invalidate_blocks() {
+        let result = interpreter_patches_block::run_input(&[]).unwrap();
+        assert_eq!(result.output, vec![5]);
+        assert_eq!(patches_loop::run_input(&[]).unwrap().output, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn puzzle_inputs() {
+        for &input in &[1, 5] {
+            check(day5::PROGRAM, day5::run_input, &[input]);
+        }
+        for &input in &[1, 2] {
+            check(day9::PROGRAM, day9::run_input, &[input]);
+        }
+    }
+}